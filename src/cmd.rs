@@ -1,6 +1,6 @@
 
 use tiny;
-use tiny::{Rect, Font, Context, Painter};
+use tiny::{Rect, Font, Context, Painter, TextBox};
 use std::rc::{Rc};
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
@@ -40,6 +40,9 @@ pub struct Config {
    pub background_color: u8,
    pub foreground_color: u8,
    pub cursor_color: u8,
+   pub error_color: u8,
+   /// Frame drawn behind the console instead of a plain `background_color` box.
+   pub panel: Option<(tiny::Bitmap, tiny::NineSlice)>,
    pub lines: usize,
 }
 
@@ -118,6 +121,7 @@ impl Cmd {
       Err(format!("invalid command '{}'", line))
    }
 
+   /// Adds a line to the history, colour tags in `text` are honoured, see `tiny::parse_markup`.
    pub fn echo(&self, text: String) {
       self.history.borrow_mut().push(text);
 
@@ -126,6 +130,11 @@ impl Cmd {
       }
    }
 
+   /// Adds an error to the history, highlighted in `error_color`.
+   pub fn echo_error(&self, err: &str) {
+      self.echo(format!("{{{}}}Error:{{}} {}", self.config.error_color, tiny::escape_markup(err)));
+   }
+
    pub fn step(&self, ctx: &Context) {
       let mut input = self.input.borrow_mut();
       let mut cursor = self.cursor.borrow_mut();
@@ -145,7 +154,7 @@ impl Cmd {
          }
       }
 
-      if ctx.key_pressed(tiny::Key::Back) && input.len() > 0 {
+      if ctx.key_repeated(tiny::Key::Back) && input.len() > 0 {
          if input.len() == *cursor {
             input.pop();
             *cursor -= 1;
@@ -165,23 +174,23 @@ impl Cmd {
 
       if ctx.key_pressed(tiny::Key::Return) && input.len() > 0 {
          let command = input.iter().collect::<String>();
-         self.echo(format!(">{}", &command));
+         self.echo(format!(">{}", tiny::escape_markup(&command)));
          input.clear();
          *cursor = 0;
 
          match self.exec(command) {
-            Ok(result) => self.echo(result),
-            Err(err) => self.echo(format!("Error: {}", err)),
+            Ok(result) => self.echo(tiny::escape_markup(&result)),
+            Err(err) => self.echo_error(&err),
          }
       }
 
-      if ctx.key_pressed(tiny::Key::Left) {
+      if ctx.key_repeated(tiny::Key::Left) {
          if *cursor > 0 {
             *cursor -= 1;
          }
       }
 
-      if ctx.key_pressed(tiny::Key::Right) {
+      if ctx.key_repeated(tiny::Key::Right) {
          if *cursor < input.len() {
             *cursor += 1;
          }
@@ -198,20 +207,27 @@ impl Cmd {
 
       let background_rect = Rect::new(0, 0, w as i32, h + 2);
 
-      painter.clip(None);
-      painter.rect_fill(background_rect, self.config.background_color);
+      painter.push_clip(background_rect);
+      match self.config.panel {
+         Some((ref bitmap, ref slice)) => painter.nine_slice(background_rect, bitmap, slice, 0, 0),
+         None => painter.rect_fill(background_rect, self.config.background_color),
+      }
       
-      painter.clip(Some(background_rect.tr(2, 2).grow(-4, -4)));
+      // Text is drawn in the coordinates of the area inside the border
+      painter.push_translation(2, 2);
+      painter.push_clip(Rect::new_size(0, 0, w as i32 - 4, h - 2));
 
-      let mut x_input = 2;
-      let mut y_input = 2;
+      let mut x_input = 0;
+      let mut y_input = 0;
 
+      // Long lines are cut off with an ellipsis rather than running past the edge
       for line in self.history.borrow().iter().skip(self.scrolling.get()).take(self.config.lines - 1) {
-         painter.text(x_input, y_input, &line, self.config.foreground_color, &self.config.font);
+         let text_box = TextBox::new(Rect::new_size(x_input, y_input, w as i32 - 4, line_height)).with_wrap(false).with_ellipsis(true);
+         painter.text_layout(line, &self.config.font.layout(line, &text_box), self.config.foreground_color, &self.config.font);
          y_input += line_height;
       }
 
-      x_input = 2;
+      x_input = 0;
       let (dx, _) = painter.char(x_input, y_input, '>', self.config.foreground_color, &self.config.font);
       x_input += dx;
 
@@ -219,7 +235,7 @@ impl Cmd {
       let cursor = *self.cursor.borrow();
       for ch in self.input.borrow().iter() {
          if cursor == pos {
-            painter.rect_fill(Rect::new_size(x_input, y_input, char_width, self.config.font.char_height), self.config.cursor_color);
+            painter.rect_fill(Rect::new_size(x_input, y_input, self.config.font.advance(*ch), char_height), self.config.cursor_color);
          }
 
          let (dx, _) = painter.char(x_input, y_input, *ch, self.config.foreground_color, &self.config.font);
@@ -231,6 +247,8 @@ impl Cmd {
          painter.rect_fill(Rect::new_size(x_input, y_input, char_width, char_height), self.config.cursor_color);
       }
 
-      painter.clip(None);
+      painter.pop_clip();
+      painter.pop_translation();
+      painter.pop_clip();
    }
 }
\ No newline at end of file
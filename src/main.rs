@@ -12,16 +12,30 @@ use tiny::default_font;
 use tiny::palette::dawn_bringer as pal;
 
 use std::rc::{Rc};
+use std::path::Path;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 200;
+
+const CONTROLS_PATH: &'static str = "controls.cfg";
+
+const DEFAULT_CONTROLS: &'static str = "\
+toggle-console = tab
+screenshot = snapshot
+quit = escape
+";
 
 
 struct App {
    game: Rc<game::Game>,
    cmd: Rc<cmd::Cmd>,
    font: Font,
+   swatches: Canvas,
    show_console: bool,
    mouse_pos: (u32, u32),
 
    show_performance: cmd::Var,
+   record_gif: cmd::Var,
 }
 
 
@@ -29,6 +43,17 @@ impl Application for App {
    fn new(ctx: &mut tiny::Context) -> Result<App, String> {
       ctx.set_palette(pal::create_palette());
 
+      // Controls saved by the player replace the defaults action by action
+      let mut controls = Actions::parse(DEFAULT_CONTROLS)?;
+      let controls_path = Path::new(CONTROLS_PATH);
+      if controls_path.exists() {
+         match Actions::load(controls_path) {
+            Ok(saved) => controls.merge(&saved),
+            Err(err) => ctx.log(LogLevel::Error, &err),
+         }
+      }
+      ctx.set_actions(controls);
+
       let font = default_font::font_4x10();
 
       // Create command
@@ -37,6 +62,8 @@ impl Application for App {
          background_color: pal::VALHALLA,
          foreground_color: pal::WHITE,
          cursor_color: pal::CORNFLOWER,
+         error_color: pal::MANDY,
+         panel: Some(console_panel()),
          lines: 10
       }));
 
@@ -50,10 +77,12 @@ impl Application for App {
          game: game,
          cmd: cmd.clone(),
          font: font,
+         swatches: Canvas::new(WIDTH, HEIGHT),
          show_console: false,
          mouse_pos: (0, 0),
          
          show_performance: cmd.register_var("show-performance", 0).unwrap(),
+         record_gif: cmd.register_var("record-gif", 0).unwrap(),
       })
    }
    
@@ -66,41 +95,61 @@ impl Application for App {
          println!("Left Mouse Clicked");
       }
 
-      if ctx.key_pressed(tiny::Key::Tab) {
+      if ctx.action_pressed("toggle-console") {
          self.show_console = !self.show_console;
       }
 
-      !ctx.key_down(tiny::Key::Escape)
+      if ctx.action_pressed("screenshot") {
+         let path = ctx.screenshot();
+         self.cmd.echo(format!("Saved {}", escape_markup(&path.display().to_string())));
+      }
+
+      if self.record_gif.get_bool() != ctx.recording() {
+         let result = if self.record_gif.get_bool() {
+            ctx.record().map(|path| format!("Recording to {}", escape_markup(&path.display().to_string())))
+         } else {
+            ctx.stop_recording().map(|_| String::from("Recording stopped"))
+         };
+
+         match result {
+            Ok(msg) => self.cmd.echo(msg),
+            Err(err) => {
+               self.cmd.echo_error(&err);
+               self.record_gif.set(0);
+            },
+         }
+      }
+
+      !ctx.action_down("quit")
    }
 
-   fn paint(&self, ctx: &tiny::Context, painter: &tiny::Painter) {
+   fn paint(&self, ctx: &tiny::Context, painter: &tiny::Painter, _alpha: f32) {
       painter.clear(pal::BLACK);
 
-      let names = pal::names();
+      // The palette swatches never change, so they are painted once and reused
+      self.swatches.paint_if_dirty(|p| {
+         let names = pal::names();
 
-      let bw = 80;
-      let bh = 25;
-      let mut x = 0;
-      let mut y = 0;      
-      for color in 1..33 {
-         let r = Rect::new_size(x, y, bw, bh);
-         let txt = self.font.measure(&names[color]);
+         let bw = 80;
+         let bh = 25;
+         for color in 1..33 {
+            p.push_translation((color as i32 - 1) % 4 * bw, (color as i32 - 1) / 4 * bh);
 
-         painter.clip(Some(r));
-         painter.rect_fill(r, color as u8);
+            let r = Rect::new_size(0, 0, bw, bh);
+            let txt = self.font.measure(&names[color]);
 
-         let text_color = if color as u8 == pal::WHITE { pal::BLACK } else { pal::WHITE };
+            p.push_clip(r);
+            p.rect_fill(r, color as u8);
 
-         let txt_x = r.width() / 2 - txt.width() / 2;
-         let txt_y = r.height() / 2 - txt.height() / 2;
-         painter.text(r.left + txt_x, r.top + txt_y, &names[color], text_color, &self.font);
+            let text_color = if color as u8 == pal::WHITE { pal::BLACK } else { pal::WHITE };
+            p.text(r.width() / 2 - txt.width() / 2, r.height() / 2 - txt.height() / 2, &names[color], text_color, &self.font);
 
-         x += bw;
-         if x >= 320 {
-            x = 0;
-            y += bh;
+            p.pop_clip();
+            p.pop_translation();
          }
-      }
+      });
+
+      painter.blit(0, 0, &self.swatches.bitmap(), self.swatches.rect(), 0, 0);
 
       if self.show_performance.get_bool() {
          ctx.draw_timing(painter, &self.font, pal::VALHALLA, pal::WHITE);
@@ -112,8 +161,33 @@ impl Application for App {
    }
 }
 
+/// Console frame with a highlighted bottom edge, drawn in code until there is skin art.
+fn console_panel() -> (Bitmap, NineSlice) {
+   let mut bitmap = Bitmap::new(5, 5);
+   {
+      let p = BitmapPainter::new(&mut bitmap);
+      p.clear(pal::VALHALLA);
+      p.rect_fill(Rect::new_size(0, 4, 5, 1), pal::DEEP_KOAMARU);
+      p.rect_fill(Rect::new_size(0, 3, 5, 1), pal::CORNFLOWER);
+   }
+
+   (bitmap, NineSlice::new(Rect::new_size(0, 0, 5, 5), 2, 2, 2, 2))
+}
+
+/// Value following `flag` on the command line, like the file in `--replay-input bug.rec`.
+fn arg(flag: &str) -> Option<String> {
+   let args = std::env::args().collect::<Vec<_>>();
+   args.iter().position(|arg| arg == flag).and_then(|idx| args.get(idx + 1).cloned())
+}
+
 fn main() {
-   if let Err(err) = tiny::run::<App>("Tiny RTS", 320, 200, 3) {
+   let config = tiny::Config::new("Tiny RTS", WIDTH, HEIGHT)
+      .with_scale(3)
+      .with_resizable(true)
+      .with_input_recording(arg("--record-input"))
+      .with_input_playback(arg("--replay-input"));
+
+   if let Err(err) = tiny::run::<App>(config) {
       println!("Error: {}", err);
    }
 }
\ No newline at end of file
@@ -58,7 +58,7 @@ impl Application for App {
       !ctx.key_down(tiny::Key::Escape)
    }
 
-   fn paint(&self, painter: &tiny::Painter) {
+   fn paint(&self, _ctx: &tiny::Context, painter: &tiny::Painter, _alpha: f32) {
       painter.clear(pal::BLACK);
 
       let names = pal::names();
@@ -92,7 +92,7 @@ impl Application for App {
 }
 
 fn main() {
-   if let Err(err) = tiny::run::<App>("Dawn Bringer Palette", 320, 200, 3) {
+   if let Err(err) = tiny::run::<App>(tiny::Config::new("Dawn Bringer Palette", 320, 200).with_scale(3)) {
       println!("Error: {}", err);
    }
 }
\ No newline at end of file
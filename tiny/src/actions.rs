@@ -0,0 +1,308 @@
+use super::*;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// The key or mouse button that triggers a binding.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Trigger {
+   Key(Key),
+   Mouse(Mouse),
+}
+
+/// A key or mouse button, optionally held together with modifiers like `ctrl+1`.
+///
+/// The modifiers have to match exactly, so `mouse-left` doesn't fire while shift is held
+/// and `shift+mouse-left` can be bound to something else.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Binding {
+   pub modifiers: Modifiers,
+   pub trigger: Trigger,
+}
+
+impl Binding {
+   pub fn key(key: Key) -> Binding {
+      Binding {
+         modifiers: Modifiers::default(),
+         trigger: Trigger::Key(key),
+      }
+   }
+
+   pub fn mouse(button: Mouse) -> Binding {
+      Binding {
+         modifiers: Modifiers::default(),
+         trigger: Trigger::Mouse(button),
+      }
+   }
+
+   pub fn with_modifiers(mut self, modifiers: Modifiers) -> Binding {
+      self.modifiers = modifiers;
+      self
+   }
+
+   /// Parses bindings like `tab`, `ctrl+1` or `shift+mouse-left`.
+   pub fn parse(text: &str) -> Result<Binding, String> {
+      let mut modifiers = Modifiers::default();
+      let mut parts = text.trim().split('+').map(|p| p.trim().to_lowercase()).collect::<Vec<_>>();
+
+      let trigger = match parts.pop() {
+         Some(ref name) if name.starts_with("mouse-") => Mouse::from_name(&name["mouse-".len()..]).map(Trigger::Mouse),
+         Some(ref name) => Key::from_name(name).map(Trigger::Key),
+         None => None,
+      };
+
+      let trigger = match trigger {
+         Some(trigger) => trigger,
+         None => return Err(format!("Unknown key or button in '{}'", text.trim())),
+      };
+
+      for part in parts {
+         match part.as_str() {
+            "shift" => modifiers.shift = true,
+            "ctrl" => modifiers.ctrl = true,
+            "alt" => modifiers.alt = true,
+            "super" => modifiers.logo = true,
+            _ => return Err(format!("Unknown modifier '{}' in '{}'", part, text.trim())),
+         }
+      }
+
+      Ok(Binding {
+         modifiers: modifiers,
+         trigger: trigger,
+      })
+   }
+
+   pub fn down(&self, ctx: &Context) -> bool {
+      let down = match self.trigger {
+         Trigger::Key(key) => ctx.key_down(key),
+         Trigger::Mouse(button) => ctx.mouse_down(button),
+      };
+
+      down && self.modifiers_match(ctx)
+   }
+
+   pub fn pressed(&self, ctx: &Context) -> bool {
+      let pressed = match self.trigger {
+         Trigger::Key(key) => ctx.key_pressed(key),
+         Trigger::Mouse(button) => ctx.mouse_pressed(button),
+      };
+
+      pressed && self.modifiers_match(ctx)
+   }
+
+   /// Modifiers are not checked on release, they are often let go of first.
+   pub fn released(&self, ctx: &Context) -> bool {
+      match self.trigger {
+         Trigger::Key(key) => ctx.key_released(key),
+         Trigger::Mouse(button) => ctx.mouse_released(button),
+      }
+   }
+
+   fn modifiers_match(&self, ctx: &Context) -> bool {
+      let mut held = ctx.modifiers();
+
+      // A modifier key bound on its own holds itself
+      match self.trigger {
+         Trigger::Key(Key::LShift) | Trigger::Key(Key::RShift) => held.shift = self.modifiers.shift,
+         Trigger::Key(Key::LControl) | Trigger::Key(Key::RControl) => held.ctrl = self.modifiers.ctrl,
+         Trigger::Key(Key::LAlt) | Trigger::Key(Key::RAlt) => held.alt = self.modifiers.alt,
+         Trigger::Key(Key::LWin) | Trigger::Key(Key::RWin) => held.logo = self.modifiers.logo,
+         _ => (),
+      }
+
+      held == self.modifiers
+   }
+}
+
+impl fmt::Display for Binding {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      let modifiers = [(self.modifiers.shift, "shift"), (self.modifiers.ctrl, "ctrl"), (self.modifiers.alt, "alt"), (self.modifiers.logo, "super")];
+      for &(held, name) in modifiers.iter() {
+         if held {
+            write!(f, "{}+", name)?;
+         }
+      }
+
+      match self.trigger {
+         Trigger::Key(key) => write!(f, "{}", key.name()),
+         Trigger::Mouse(button) => write!(f, "mouse-{}", button.name()),
+      }
+   }
+}
+
+
+/// Named actions, each bound to any number of keys, buttons and chords.
+///
+/// Bindings are stored one action per line:
+///
+/// ```text
+/// # Comments start with a hash
+/// toggle-console = tab, grave
+/// add-to-selection = shift+mouse-left
+/// select-group-1 = 1
+/// set-group-1 = ctrl+1
+/// ```
+#[derive(Clone, Default)]
+pub struct Actions {
+   actions: Vec<(String, Vec<Binding>)>,
+}
+
+impl Actions {
+   pub fn new() -> Actions {
+      Actions {
+         actions: Vec::new(),
+      }
+   }
+
+   /// Adds a binding to an action, creating the action if needed.
+   pub fn bind(&mut self, action: &str, binding: Binding) {
+      match self.actions.iter().position(|entry| entry.0 == action) {
+         Some(idx) => {
+            if !self.actions[idx].1.contains(&binding) {
+               self.actions[idx].1.push(binding);
+            }
+         },
+         None => self.actions.push((String::from(action), vec![binding])),
+      }
+   }
+
+   /// Replaces all bindings of an action.
+   pub fn rebind(&mut self, action: &str, bindings: &[Binding]) {
+      self.unbind(action);
+      for binding in bindings {
+         self.bind(action, *binding);
+      }
+   }
+
+   /// Removes all bindings of an action.
+   pub fn unbind(&mut self, action: &str) {
+      self.actions.retain(|entry| entry.0 != action);
+   }
+
+   pub fn bindings(&self, action: &str) -> &[Binding] {
+      self.actions.iter()
+         .find(|entry| entry.0 == action)
+         .map(|entry| entry.1.as_slice())
+         .unwrap_or(&[])
+   }
+
+   pub fn names(&self) -> Vec<&str> {
+      self.actions.iter().map(|entry| entry.0.as_str()).collect()
+   }
+
+   /// Adds the bindings from `other`, replacing actions that both have.
+   pub fn merge(&mut self, other: &Actions) {
+      for (name, bindings) in other.actions.iter() {
+         self.rebind(name, bindings);
+      }
+   }
+
+   pub fn down(&self, ctx: &Context, action: &str) -> bool {
+      self.bindings(action).iter().any(|b| b.down(ctx))
+   }
+
+   pub fn pressed(&self, ctx: &Context, action: &str) -> bool {
+      self.bindings(action).iter().any(|b| b.pressed(ctx))
+   }
+
+   pub fn released(&self, ctx: &Context, action: &str) -> bool {
+      self.bindings(action).iter().any(|b| b.released(ctx))
+   }
+
+   pub fn parse(text: &str) -> Result<Actions, String> {
+      let mut actions = Actions::new();
+
+      for (idx, line) in text.lines().enumerate() {
+         let line = line.trim();
+         if line.is_empty() || line.starts_with('#') {
+            continue;
+         }
+
+         let (name, bindings) = match line.find('=') {
+            Some(split) => (line[..split].trim(), &line[split + 1..]),
+            None => return Err(format!("Line {}: expected 'action = bindings'", idx + 1)),
+         };
+
+         if name.is_empty() {
+            return Err(format!("Line {}: missing action name", idx + 1));
+         }
+
+         // An action without bindings is kept, so a file can unbind a default when merged
+         actions.unbind(name);
+         actions.actions.push((String::from(name), Vec::new()));
+
+         for binding in bindings.split(',').filter(|b| !b.trim().is_empty()) {
+            match Binding::parse(binding) {
+               Ok(binding) => actions.bind(name, binding),
+               Err(err) => return Err(format!("Line {}: {}", idx + 1, err)),
+            }
+         }
+      }
+
+      Ok(actions)
+   }
+
+   pub fn load(path: &Path) -> Result<Actions, String> {
+      let mut text = String::new();
+      if let Err(err) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+         return Err(format!("Could not load bindings {}: {}", path.display(), err));
+      }
+
+      Actions::parse(&text)
+   }
+
+   pub fn save(&self, path: &Path) -> Result<(), String> {
+      match File::create(path).and_then(|mut f| f.write_all(self.to_string().as_bytes())) {
+         Ok(_) => Ok(()),
+         Err(err) => Err(format!("Could not save bindings {}: {}", path.display(), err)),
+      }
+   }
+}
+
+impl fmt::Display for Actions {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      for (name, bindings) in self.actions.iter() {
+         let bindings = bindings.iter().map(|b| b.to_string()).collect::<Vec<_>>();
+         writeln!(f, "{} = {}", name, bindings.join(", "))?;
+      }
+
+      Ok(())
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parse_and_print() {
+      let text = "# Controls\ntoggle-console = Tab, grave\n\nset-group-1 = ctrl+1\nadd = shift+Mouse-Left\nnothing =\n";
+      let actions = Actions::parse(text).unwrap();
+
+      assert_eq!(actions.names(), ["toggle-console", "set-group-1", "add", "nothing"]);
+      assert!(actions.bindings("toggle-console") == [Binding::key(Key::Tab), Binding::key(Key::Grave)]);
+      assert!(actions.bindings("set-group-1") == [Binding::key(Key::Key1).with_modifiers(Modifiers { ctrl: true, ..Modifiers::default() })]);
+      assert!(actions.bindings("missing").is_empty());
+
+      let printed = actions.to_string();
+      assert_eq!(printed, "toggle-console = tab, grave\nset-group-1 = ctrl+1\nadd = shift+mouse-left\nnothing = \n");
+      assert_eq!(Actions::parse(&printed).unwrap().to_string(), printed);
+
+      assert!(Actions::parse("jump = hyper+space").is_err());
+      assert!(Actions::parse("jump = spacebar").is_err());
+      assert!(Actions::parse("jump").is_err());
+   }
+
+   #[test]
+   fn merge_and_rebind() {
+      let mut actions = Actions::parse("quit = escape\nselect = mouse-left").unwrap();
+      actions.merge(&Actions::parse("quit = q").unwrap());
+      assert!(actions.bindings("quit") == [Binding::key(Key::Q)]);
+      assert!(actions.bindings("select") == [Binding::mouse(Mouse::Left)]);
+
+      actions.unbind("select");
+      assert_eq!(actions.names(), ["quit"]);
+   }
+}
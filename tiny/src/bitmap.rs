@@ -1,13 +1,21 @@
 
 use super::*;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::rc::Rc;
+use std::cmp;
+use std::f32;
+use std::f32::consts;
 use std::ptr;
 use std::path::Path;
 use std::result::Result;
 
 use image;
-use image::GenericImage;
+use png;
+use png::HasParameters;
 
 #[derive(Clone)]
 pub struct Bitmap {
@@ -28,25 +36,100 @@ impl Bitmap {
       }
    }
 
+   /// Loads an image, adding every colour that isn't in the palette yet.
    pub fn load(ctx: &Context, path: &Path) -> Result<Bitmap, String> {
-      if let Ok(ref img) = image::open(path) {
-         let (w, h) = img.dimensions();
+      Bitmap::load_with(ctx, path, LoadMode::Extend)
+   }
 
-         let bitmap = Bitmap::new(w, h);
+   pub fn load_with(ctx: &Context, path: &Path, mode: LoadMode) -> Result<Bitmap, String> {
+      let img = match image::open(path) {
+         Ok(img) => img.to_rgba(),
+         Err(err) => return Err(format!("Could not load {}: {}", path.display(), err)),
+      };
 
-         {
-            let mut pixels = bitmap.pixels.borrow_mut();
+      let (w, h) = img.dimensions();
 
-            for (x, y, pixel) in img.pixels() {
-               let color = ctx.palette_add(Color::new(pixel[0], pixel[1], pixel[2], pixel[3]));
-               pixels[(x + y * w) as usize] = color;
+      match mode {
+         LoadMode::Extend => {
+            match extend_palette(Rc::make_mut(&mut ctx.palette.borrow_mut()), &img, w, h) {
+               Ok(bitmap) => Ok(bitmap),
+               Err(err) => Err(format!("Could not load {}: {}", path.display(), err)),
             }
-         }
+         },
+         LoadMode::Nearest(dither) => Ok(Bitmap::from_rgba(&img, w, h, &ctx.palette.borrow(), dither)),
+      }
+   }
 
-         Ok(bitmap)
-      } else {
-         Err(String::from("Could not load image"))
+   /// Maps RGBA pixel data onto the palette, every pixel gets the nearest palette entry.
+   /// Pixels that are less than half opaque become transparent.
+   pub fn from_rgba(rgba: &[u8], width: u32, height: u32, palette: &Palette, dither: Dither) -> Bitmap {
+      let bitmap = Bitmap::new(width, height);
+
+      {
+         let mut pixels = bitmap.pixels.borrow_mut();
+         let mut cache: HashMap<u32, u8> = HashMap::new();
+         let spread = if dither == Dither::Ordered { dither_spread(palette) } else { 0.0 };
+
+         // Quantisation error carried to the neighbouring pixels, for the current and next row
+         let row_len = width as usize + 2;
+         let mut error = vec![[0f32; 3]; row_len * 2];
+
+         for y in 0..height as usize {
+            for x in 0..width as usize {
+               let i = x + y * width as usize;
+               let src = &rgba[i * 4..i * 4 + 4];
+
+               if src[3] < 128 {
+                  pixels[i] = TRANSPARENT;
+                  continue;
+               }
+
+               let mut rgb = [src[0] as f32, src[1] as f32, src[2] as f32];
+
+               match dither {
+                  Dither::None => (),
+                  Dither::Ordered => {
+                     let offset = (BAYER_4X4[y % 4][x % 4] as f32 / 16.0 - 0.5) * spread;
+                     for c in rgb.iter_mut() {
+                        *c += offset;
+                     }
+                  },
+                  Dither::FloydSteinberg => {
+                     let e = error[(y % 2) * row_len + x + 1];
+                     for c in 0..3 {
+                        rgb[c] += e[c];
+                     }
+                  },
+               }
+
+               let color = Color::new(clamp_channel(rgb[0]), clamp_channel(rgb[1]), clamp_channel(rgb[2]), 255);
+               let idx = *cache.entry(color.rgba).or_insert_with(|| palette.nearest(color));
+               pixels[i] = idx;
+
+               if dither == Dither::FloydSteinberg {
+                  let chosen = palette.colors[idx as usize];
+                  let diff = [rgb[0] - chosen.red() as f32, rgb[1] - chosen.green() as f32, rgb[2] - chosen.blue() as f32];
+
+                  let current = (y % 2) * row_len + x + 1;
+                  let next = ((y + 1) % 2) * row_len + x + 1;
+                  for c in 0..3 {
+                     error[current + 1][c] += diff[c] * 7.0 / 16.0;
+                     error[next - 1][c] += diff[c] * 3.0 / 16.0;
+                     error[next][c] += diff[c] * 5.0 / 16.0;
+                     error[next + 1][c] += diff[c] * 1.0 / 16.0;
+                  }
+               }
+            }
+
+            // The current row becomes the row after next
+            let current = (y % 2) * row_len;
+            for e in error[current..current + row_len].iter_mut() {
+               *e = [0.0; 3];
+            }
+         }
       }
+
+      bitmap
    }
 
    pub fn from_bitmask(mask: &[u8], width: u32, height: u32) -> Bitmap {
@@ -69,6 +152,61 @@ impl Bitmap {
       }
    }
 
+   /// Looks up every pixel in the palette, returning RGBA data. Indices outside the palette
+   /// become transparent.
+   pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+      let transparent = Color::new(0, 0, 0, 0);
+      let pixels = self.pixels.borrow();
+      let mut rgba = Vec::with_capacity(pixels.len() * 4);
+
+      for idx in pixels.iter() {
+         let color = palette.colors.get(*idx as usize).unwrap_or(&transparent);
+         rgba.extend_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+      }
+
+      rgba
+   }
+
+   /// Saves the bitmap as an RGBA PNG.
+   pub fn save(&self, path: &Path, palette: &Palette) -> Result<(), String> {
+      match image::save_buffer(path, &self.to_rgba(palette), self.width, self.height, image::RGBA(8)) {
+         Ok(_) => Ok(()),
+         Err(err) => Err(format!("Could not save {}: {}", path.display(), err)),
+      }
+   }
+
+   /// Saves the bitmap as an indexed PNG, keeping the palette indices intact.
+   pub fn save_indexed(&self, path: &Path, palette: &Palette) -> Result<(), String> {
+      let err = |err: png::EncodingError| format!("Could not save {}: {}", path.display(), err);
+
+      let file = match File::create(path) {
+         Ok(file) => file,
+         Err(err) => return Err(format!("Could not save {}: {}", path.display(), err)),
+      };
+
+      let mut plte = Vec::with_capacity(palette.colors.len() * 3);
+      let mut trns = Vec::with_capacity(palette.colors.len());
+      for color in palette.colors.iter().take(256) {
+         plte.extend_from_slice(&[color.red(), color.green(), color.blue()]);
+         trns.push(color.alpha());
+      }
+
+      // Entries after the last translucent one default to opaque
+      while trns.last() == Some(&255) {
+         trns.pop();
+      }
+
+      let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+      encoder.set(png::ColorType::Indexed).set(png::BitDepth::Eight);
+
+      let mut writer = encoder.write_header().map_err(&err)?;
+      writer.write_chunk(*b"PLTE", &plte).map_err(&err)?;
+      if !trns.is_empty() {
+         writer.write_chunk(*b"tRNS", &trns).map_err(&err)?;
+      }
+      writer.write_image_data(&self.pixels.borrow()).map_err(&err)
+   }
+
    #[inline]
    pub fn pixel(&self, x: u32, y: u32) -> u8 {
       self.pixels.borrow()[(self.width * y + x) as usize]
@@ -76,9 +214,103 @@ impl Bitmap {
 }
 
 
+/// How `Bitmap::load_with` maps image colours onto the palette.
+#[derive(Copy, Clone, PartialEq)]
+pub enum LoadMode {
+   /// Adds every unseen colour to the palette, fails if the palette would grow past 256 colours.
+   Extend,
+   /// Maps every pixel to the nearest existing palette entry.
+   Nearest(Dither),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Dither {
+   None,
+   /// 4x4 Bayer matrix.
+   Ordered,
+   /// Floyd-Steinberg error diffusion.
+   FloydSteinberg,
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [
+   [0, 8, 2, 10],
+   [12, 4, 14, 6],
+   [3, 11, 1, 9],
+   [15, 7, 13, 5],
+];
+
+/// How far, per channel, ordered dithering pushes a colour. This is the average distance
+/// from every palette entry to its closest neighbour, so sparse palettes get more dithering.
+fn dither_spread(palette: &Palette) -> f32 {
+   let colors = palette.colors.iter().filter(|c| c.alpha() > 0).collect::<Vec<_>>();
+   if colors.len() < 2 {
+      return 0.0;
+   }
+
+   let mut total = 0.0;
+   for (i, a) in colors.iter().enumerate() {
+      let closest = colors.iter().enumerate()
+         .filter(|&(j, _)| i != j)
+         .map(|(_, b)| {
+            let dr = a.red() as f32 - b.red() as f32;
+            let dg = a.green() as f32 - b.green() as f32;
+            let db = a.blue() as f32 - b.blue() as f32;
+            ((dr * dr + dg * dg + db * db) / 3.0).sqrt()
+         })
+         .fold(f32::MAX, f32::min);
+
+      total += closest;
+   }
+
+   total / colors.len() as f32
+}
+
+#[inline]
+fn clamp_channel(c: f32) -> u8 {
+   c.round().max(0.0).min(255.0) as u8
+}
+
+/// Adds the colours of the image to the palette, without touching it if they don't all fit.
+fn extend_palette(palette: &mut Palette, rgba: &[u8], width: u32, height: u32) -> Result<Bitmap, String> {
+   let mut new_colors: Vec<Color> = Vec::new();
+
+   for p in rgba.chunks(4) {
+      let color = Color::new(p[0], p[1], p[2], p[3]);
+      if palette.index_of(color).is_none() && !new_colors.iter().any(|c| c.rgba == color.rgba) {
+         new_colors.push(color);
+      }
+   }
+
+   if palette.colors.len() + new_colors.len() > 256 {
+      return Err(format!("image needs {} new colours but the palette only has room for {}",
+                         new_colors.len(), 256 - palette.colors.len()));
+   }
+
+   let bitmap = Bitmap::new(width, height);
+
+   {
+      let mut pixels = bitmap.pixels.borrow_mut();
+      let mut cache: HashMap<u32, u8> = HashMap::new();
+
+      for (i, p) in rgba.chunks(4).enumerate() {
+         let color = Color::new(p[0], p[1], p[2], p[3]);
+         pixels[i] = *cache.entry(color.rgba).or_insert_with(|| palette.add_color(color).unwrap());
+      }
+   }
+
+   Ok(bitmap)
+}
+
+
 pub struct BitmapPainter<'a> {
    target: &'a mut Bitmap,
    clip: RefCell<Rect>,
+   blend: RefCell<Option<Rc<BlendTable>>>,
+   palette: RefCell<Option<Rc<Palette>>>,
+   origin: Cell<(i32, i32)>,
+   translations: RefCell<Vec<(i32, i32)>>,
+   /// Clip rects from before each `push_clip`, along with the rect pushed.
+   clips: RefCell<Vec<(Rect, Rect)>>,
 }
 
 impl<'a> BitmapPainter<'a> {
@@ -89,6 +321,174 @@ impl<'a> BitmapPainter<'a> {
       BitmapPainter {
          target: target,
          clip: RefCell::new(Rect::new_size(0, 0, w as i32, h as i32)),
+         blend: RefCell::new(None),
+         palette: RefCell::new(None),
+         origin: Cell::new((0, 0)),
+         translations: RefCell::new(Vec::new()),
+         clips: RefCell::new(Vec::new()),
+      }
+   }
+
+   /// Colour after a markup tag, names not in the palette leave it unchanged.
+   fn markup_color(&self, tag: ColorTag, color: u8, base: u8) -> u8 {
+      match tag {
+         ColorTag::Reset => base,
+         ColorTag::Index(idx) => idx,
+         ColorTag::Name(name) => self.palette.borrow().as_ref().and_then(|palette| palette.find(name)).unwrap_or(color),
+      }
+   }
+
+   /// The clip rect in the coordinates drawing happens in, moved by the translation.
+   #[inline]
+   fn local_clip(&self) -> Rect {
+      let (x, y) = self.origin.get();
+      self.clip.borrow().tr(-x, -y)
+   }
+
+   /// Index in the target pixels of the translated point `x`, `y`.
+   #[inline]
+   fn index(&self, x: i32, y: i32) -> usize {
+      let (origin_x, origin_y) = self.origin.get();
+      ((x + origin_x) + (y + origin_y) * self.target.width as i32) as usize
+   }
+
+   /// Writes a single pixel, the clip rect excludes its right and bottom edge.
+   #[inline]
+   fn plot(&self, pixels: &mut Vec<u8>, clip: &Rect, table: Option<&Rc<BlendTable>>, x: i32, y: i32, color: u8) {
+      if x >= clip.left && x < clip.right && y >= clip.top && y < clip.bottom {
+         let p = &mut pixels[self.index(x, y)];
+         *p = mix(table, *p, color);
+      }
+   }
+
+   /// Fills the horizontal span `x0..=x1` on row `y`.
+   fn span(&self, x0: i32, x1: i32, y: i32, color: u8) {
+      let clip = self.local_clip();
+      if y < clip.top || y >= clip.bottom {
+         return;
+      }
+
+      let left = cmp::max(x0, clip.left);
+      let right = cmp::min(x1 + 1, clip.right);
+      if left < right {
+         let start = self.index(left, y);
+         let end = start + (right - left) as usize;
+         let table = self.blend.borrow();
+
+         for p in self.target.pixels.borrow_mut()[start..end].iter_mut() {
+            *p = mix(table.as_ref(), *p, color);
+         }
+      }
+   }
+}
+
+/// Number of clockwise quarter turns requested by the `DRAW_ROTATE_*` flags.
+#[inline]
+fn quarter_turns(flags: u32) -> i32 {
+   ((flags & DRAW_ROTATE_270) / DRAW_ROTATE_90) as i32
+}
+
+/// Maps a pixel of the flipped and rotated image back to the `w` x `h` source it came from.
+/// The source is flipped first and then rotated clockwise.
+#[inline]
+fn unrotate(x: i32, y: i32, w: i32, h: i32, flags: u32) -> (i32, i32) {
+   let (u, v) = match quarter_turns(flags) {
+      1 => (y, h - 1 - x),
+      2 => (w - 1 - x, h - 1 - y),
+      3 => (w - 1 - y, x),
+      _ => (x, y),
+   };
+
+   let u = if (flags & DRAW_FLIP_H) > 0 { w - 1 - u } else { u };
+   let v = if (flags & DRAW_FLIP_V) > 0 { h - 1 - v } else { v };
+   (u, v)
+}
+
+/// Combines a colour with the target through the blend table, if there is one.
+#[inline]
+fn mix(table: Option<&Rc<BlendTable>>, target: u8, color: u8) -> u8 {
+   match table {
+      Some(table) => table.get(target, color),
+      None => color,
+   }
+}
+
+/// Writes a source pixel onto the target, index zero is transparent.
+#[inline]
+fn put(target: &mut u8, source: u8, flags: u32, color: u8, table: Option<&Rc<BlendTable>>) {
+   if source > 0 {
+      *target = mix(table, *target, if flags & DRAW_MASK > 0 { color } else { source });
+   }
+}
+
+/// Walks the line from (x0, y0) towards (x1, y1), the end point itself is not visited.
+fn walk_line<F: FnMut(i32, i32)>(x0: i32, y0: i32, x1: i32, y1: i32, mut f: F) {
+   let sx = if x0 < x1 { 1 } else { -1 };
+   let sy = if y0 < y1 { 1 } else { -1 };
+   let dx = (x1 - x0).abs();
+   let dy = (y1 - y0).abs();
+
+   let mut err = dx - dy;
+   let mut x = x0;
+   let mut y = y0;
+
+   while x != x1 || y != y1 {
+      f(x, y);
+
+      let e2 = 2 * err;
+
+      if e2 > -dy {
+         err -= dy;
+         x += sx;
+      }
+      if e2 < dx {
+         err += dx;
+         y += sy;
+      }
+   }
+}
+
+/// Midpoint ellipse, visits every point of the first quadrant exactly once.
+fn walk_ellipse<F: FnMut(i32, i32)>(rx: i32, ry: i32, mut f: F) {
+   let rx2 = rx as i64 * rx as i64;
+   let ry2 = ry as i64 * ry as i64;
+
+   let mut x = 0i64;
+   let mut y = ry as i64;
+   let mut px = 0i64;
+   let mut py = 2 * rx2 * y;
+
+   // Region 1, the slope is less than one. Decision variables are scaled by 4.
+   let mut p = 4 * ry2 - 4 * rx2 * ry as i64 + rx2;
+   while px < py {
+      f(x as i32, y as i32);
+
+      x += 1;
+      px += 2 * ry2;
+
+      if p < 0 {
+         p += 4 * (ry2 + px);
+      } else {
+         y -= 1;
+         py -= 2 * rx2;
+         p += 4 * (ry2 + px - py);
+      }
+   }
+
+   // Region 2, step along y
+   let mut p = ry2 * (2 * x + 1) * (2 * x + 1) + 4 * rx2 * (y - 1) * (y - 1) - 4 * rx2 * ry2;
+   while y >= 0 {
+      f(x as i32, y as i32);
+
+      y -= 1;
+      py -= 2 * rx2;
+
+      if p > 0 {
+         p += 4 * (rx2 - py);
+      } else {
+         x += 1;
+         px += 2 * ry2;
+         p += 4 * (rx2 - py + px);
       }
    }
 }
@@ -99,12 +499,49 @@ impl<'a> Painter for BitmapPainter<'a> {
    }
 
    fn clip(&self, rect: Option<Rect>) {
-      match rect {
-         Some(r) => *self.clip.borrow_mut() = r.fit(0, 0, self.target.width as i32, self.target.height as i32),
-         None => *self.clip.borrow_mut() = Rect::new_size(0, 0, self.target.width as i32, self.target.height as i32),
+      let bounds = match self.clips.borrow().last() {
+         Some(&(_, pushed)) => pushed,
+         None => Rect::new_size(0, 0, self.target.width as i32, self.target.height as i32),
+      };
+
+      let (x, y) = self.origin.get();
+      *self.clip.borrow_mut() = match rect {
+         Some(r) => r.tr(x, y).intersect(bounds),
+         None => bounds,
+      };
+   }
+
+   fn push_clip(&self, rect: Rect) {
+      let (x, y) = self.origin.get();
+      let previous = *self.clip.borrow();
+      let pushed = rect.tr(x, y).intersect(previous);
+
+      self.clips.borrow_mut().push((previous, pushed));
+      *self.clip.borrow_mut() = pushed;
+   }
+
+   fn pop_clip(&self) {
+      if let Some((previous, _)) = self.clips.borrow_mut().pop() {
+         *self.clip.borrow_mut() = previous;
+      }
+   }
+
+   fn push_translation(&self, x: i32, y: i32) {
+      let (origin_x, origin_y) = self.origin.get();
+      self.translations.borrow_mut().push((origin_x, origin_y));
+      self.origin.set((origin_x + x, origin_y + y));
+   }
+
+   fn pop_translation(&self) {
+      if let Some(origin) = self.translations.borrow_mut().pop() {
+         self.origin.set(origin);
       }
    }
 
+   fn translation(&self) -> (i32, i32) {
+      self.origin.get()
+   }
+
    fn clear(&self, color: u8) {
       unsafe {
          let len = (self.target.width * self.target.height) as usize;
@@ -112,10 +549,16 @@ impl<'a> Painter for BitmapPainter<'a> {
       }
    }
 
+   fn blend(&self, table: Option<Rc<BlendTable>>) {
+      *self.blend.borrow_mut() = table;
+   }
+
+   fn palette(&self, palette: Option<Rc<Palette>>) {
+      *self.palette.borrow_mut() = palette;
+   }
+
    fn pixel(&self, x: i32, y: i32, color: u8) {
-      if self.clip.borrow().inside(x, y) {
-         self.target.pixels.borrow_mut()[(x + y * self.target.width as i32) as usize] = color;
-      }
+      self.plot(&mut self.target.pixels.borrow_mut(), &self.local_clip(), self.blend.borrow().as_ref(), x, y, color);
    }
 
    fn rect_stroke(&self, rect: Rect, color: u8) {
@@ -126,78 +569,310 @@ impl<'a> Painter for BitmapPainter<'a> {
    }
 
    fn rect_fill(&self, rect: Rect, color: u8) {
-      let stride = self.target.width as isize;
-      let start = (rect.top * self.target.width as i32) + rect.left;
-      let len = (rect.right - rect.left) as usize;
+      for y in rect.top..rect.bottom {
+         self.span(rect.left, rect.right - 1, y, color);
+      }
+   }
+
+   fn line(&self, x0: i32, y0: i32, x1: i32, y1: i32, color: u8)
+   {
+      let clip = self.local_clip();
+      let table = self.blend.borrow();
       let mut pixels = self.target.pixels.borrow_mut();
 
-      unsafe {
-         let mut pos = start as isize;
-         for _ in 0..(rect.bottom - rect.top) as i32 {
-            ptr::write_bytes(pixels.as_mut_ptr().offset(pos), color, len);
-            pos += stride;
+      walk_line(x0, y0, x1, y1, |x, y| self.plot(&mut pixels, &clip, table.as_ref(), x, y, color));
+   }
+
+   fn circle_stroke(&self, x: i32, y: i32, radius: i32, color: u8) {
+      self.ellipse_stroke(x, y, radius, radius, color);
+   }
+
+   fn circle_fill(&self, x: i32, y: i32, radius: i32, color: u8) {
+      self.ellipse_fill(x, y, radius, radius, color);
+   }
+
+   fn ellipse_stroke(&self, cx: i32, cy: i32, radius_x: i32, radius_y: i32, color: u8) {
+      if radius_x < 0 || radius_y < 0 {
+         return;
+      }
+
+      if radius_y == 0 {
+         self.span(cx - radius_x, cx + radius_x, cy, color);
+         return;
+      }
+
+      let clip = self.local_clip();
+      let table = self.blend.borrow();
+      let mut pixels = self.target.pixels.borrow_mut();
+
+      walk_ellipse(radius_x, radius_y, |x, y| {
+         self.plot(&mut pixels, &clip, table.as_ref(), cx + x, cy + y, color);
+         if x != 0 {
+            self.plot(&mut pixels, &clip, table.as_ref(), cx - x, cy + y, color);
+         }
+         if y != 0 {
+            self.plot(&mut pixels, &clip, table.as_ref(), cx + x, cy - y, color);
+            if x != 0 {
+               self.plot(&mut pixels, &clip, table.as_ref(), cx - x, cy - y, color);
+            }
+         }
+      });
+   }
+
+   fn ellipse_fill(&self, cx: i32, cy: i32, radius_x: i32, radius_y: i32, color: u8) {
+      if radius_x < 0 || radius_y < 0 {
+         return;
+      }
+
+      // Widest extent of every row in the lower half
+      let mut extents = vec![0; radius_y as usize + 1];
+      walk_ellipse(radius_x, radius_y, |x, y| {
+         extents[y as usize] = cmp::max(extents[y as usize], x);
+      });
+
+      for (y, x) in extents.iter().enumerate() {
+         let y = y as i32;
+         self.span(cx - x, cx + x, cy + y, color);
+         if y != 0 {
+            self.span(cx - x, cx + x, cy - y, color);
          }
       }
    }
 
-   fn line(&self, x0: i32, y0: i32, x1: i32, y1: i32, color: u8)
-   {
-      let sx = if x0 < x1 { 1 } else { -1 };
-      let sy = if y0 < y1 { 1 } else { -1 };
-      let dx = (x1 - x0).abs();
-      let dy = (y1 - y0).abs();
-
-      let mut err = dx - dy;
-      let mut x = x0;
-      let mut y = y0;
-      
-      let clip = self.clip.borrow();
+   fn triangle_stroke(&self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32, color: u8) {
+      self.polygon_stroke(&[(x0, y0), (x1, y1), (x2, y2)], color);
+   }
+
+   fn triangle_fill(&self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32, color: u8) {
+      self.polygon_fill(&[(x0, y0), (x1, y1), (x2, y2)], color);
+   }
+
+   fn polygon_stroke(&self, points: &[(i32, i32)], color: u8) {
+      if points.is_empty() {
+         return;
+      }
+
+      let clip = self.local_clip();
+      let table = self.blend.borrow();
       let mut pixels = self.target.pixels.borrow_mut();
 
+      // Every edge skips its end point, which is the start point of the next edge
+      for i in 0..points.len() {
+         let (x0, y0) = points[i];
+         let (x1, y1) = points[(i + 1) % points.len()];
 
-      while x != x1 || y != y1 {
-         if clip.inside(x, y) {
-            pixels[(x + y * self.target.width as i32) as usize] = color;
+         if points.len() == 1 {
+            self.plot(&mut pixels, &clip, table.as_ref(), x0, y0, color);
          }
 
-         let e2 = 2 * err;
+         walk_line(x0, y0, x1, y1, |x, y| self.plot(&mut pixels, &clip, table.as_ref(), x, y, color));
+      }
+   }
+
+   fn polygon_fill(&self, points: &[(i32, i32)], color: u8) {
+      if points.is_empty() {
+         return;
+      }
+
+      let (top, bottom) = {
+         let clip = self.local_clip();
+         let min_y = points.iter().map(|p| p.1).min().unwrap();
+         let max_y = points.iter().map(|p| p.1).max().unwrap();
+         (cmp::max(min_y, clip.top), cmp::min(max_y + 1, clip.bottom))
+      };
+
+      if top >= bottom {
+         return;
+      }
+
+      // Collect the covered intervals of every row, starting with the outline
+      let mut rows: Vec<Vec<(i32, i32)>> = vec![Vec::new(); (bottom - top) as usize];
+
+      for i in 0..points.len() {
+         let (x0, y0) = points[i];
+         let (x1, y1) = points[(i + 1) % points.len()];
+
+         if points.len() == 1 {
+            rows[(y0 - top) as usize].push((x0, x0));
+         }
+
+         walk_line(x0, y0, x1, y1, |x, y| {
+            if y >= top && y < bottom {
+               rows[(y - top) as usize].push((x, x));
+            }
+         });
+      }
+
+      // Even-odd crossings through the pixel centers of every row make up the inside
+      let mut crossings: Vec<f64> = Vec::new();
+      for y in top..bottom {
+         crossings.clear();
+
+         for i in 0..points.len() {
+            let (x0, y0) = points[i];
+            let (x1, y1) = points[(i + 1) % points.len()];
+
+            if y0 != y1 && y >= cmp::min(y0, y1) && y < cmp::max(y0, y1) {
+               crossings.push(x0 as f64 + (y - y0) as f64 * (x1 - x0) as f64 / (y1 - y0) as f64);
+            }
+         }
+
+         crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+         let row = &mut rows[(y - top) as usize];
+         for pair in crossings.chunks(2) {
+            if pair.len() == 2 && pair[0].ceil() <= pair[1].floor() {
+               row.push((pair[0].ceil() as i32, pair[1].floor() as i32));
+            }
+         }
+      }
+
+      for (y, row) in rows.iter_mut().enumerate() {
+         row.sort();
 
-         if e2 > -dy {
-            err -= dy;
-            x += sx;
+         // Merge overlapping and adjacent intervals so no pixel is written twice
+         let mut current: Option<(i32, i32)> = None;
+         for &(left, right) in row.iter() {
+            current = match current {
+               Some((l, r)) if left <= r + 1 => Some((l, cmp::max(r, right))),
+               Some((l, r)) => {
+                  self.span(l, r, top + y as i32, color);
+                  Some((left, right))
+               },
+               None => Some((left, right)),
+            };
          }
-         if e2 < dx {
-            err += dx;
-            y += sy;
+
+         if let Some((l, r)) = current {
+            self.span(l, r, top + y as i32, color);
          }
       }
    }
 
-   fn blit(&self, x0: i32, y0: i32, source: &Bitmap, source_rect: Rect, flags: u32, color: u8) {
-      let clip = self.clip.borrow();
+   fn blit(&self, x: i32, y: i32, source: &Bitmap, source_rect: Rect, flags: u32, color: u8) {
+      let (w, h) = if quarter_turns(flags) % 2 == 1 {
+         (source_rect.height(), source_rect.width())
+      } else {
+         (source_rect.width(), source_rect.height())
+      };
+
+      self.blit_scaled(Rect::new_size(x, y, w, h), source, source_rect, flags, color);
+   }
+
+   fn blit_scaled(&self, dest_rect: Rect, source: &Bitmap, source_rect: Rect, flags: u32, color: u8) {
+      let clip = self.local_clip();
+      let dest_w = dest_rect.width() as i64;
+      let dest_h = dest_rect.height() as i64;
+
+      if dest_w <= 0 || dest_h <= 0 || source_rect.width() <= 0 || source_rect.height() <= 0 {
+         return;
+      }
+
+      // Size of the source after rotation, this is what gets stretched over the destination
+      let turns = quarter_turns(flags);
+      let (rotated_w, rotated_h) = if turns % 2 == 1 {
+         (source_rect.height() as i64, source_rect.width() as i64)
+      } else {
+         (source_rect.width() as i64, source_rect.height() as i64)
+      };
 
+      let table = self.blend.borrow();
       let source_pixels = source.pixels.borrow();
       let mut target_pixels = self.target.pixels.borrow_mut();
 
-      for y in source_rect.top..source_rect.bottom {
-         for x in source_rect.left..source_rect.right {
-            let target_x = x0 + (x - source_rect.left);
-            let target_y = y0 + (y - source_rect.top);
+      let top = cmp::max(dest_rect.top, clip.top);
+      let bottom = cmp::min(dest_rect.bottom, clip.bottom);
+      let left = cmp::max(dest_rect.left, clip.left);
+      let right = cmp::min(dest_rect.right, clip.right);
 
-            let sx = if (flags & DRAW_FLIP_H) > 0 { source.width as i32 - x } else { x };
+      for target_y in top..bottom {
+         // Sample at the pixel center
+         let ry = ((2 * (target_y - dest_rect.top) as i64 + 1) * rotated_h / (2 * dest_h)) as i32;
 
-            if clip.inside(target_x, target_y) {
-               let target_idx = (target_x + target_y * self.target.width as i32) as usize;
-               let source_idx = (sx + y * source.width as i32) as usize;
-               let source = source_pixels[source_idx];
-               let target  = target_pixels[target_idx];
+         for target_x in left..right {
+            let rx = ((2 * (target_x - dest_rect.left) as i64 + 1) * rotated_w / (2 * dest_w)) as i32;
+            let (u, v) = unrotate(rx, ry, source_rect.width(), source_rect.height(), flags);
 
-               if flags & DRAW_MASK > 0 {
-                  target_pixels[target_idx] = if source > 0 { color } else { target };
-               } else {
-                  target_pixels[target_idx] = if source > 0 { source } else { target };
-               }
+            let source_idx = ((source_rect.left + u) + (source_rect.top + v) * source.width as i32) as usize;
+            let target_idx = self.index(target_x, target_y);
+            put(&mut target_pixels[target_idx], source_pixels[source_idx], flags, color, table.as_ref());
+         }
+      }
+   }
+
+   fn blit_rotated(&self, x: i32, y: i32, source: &Bitmap, source_rect: Rect, pivot_x: f32, pivot_y: f32, angle: f32, flags: u32, color: u8) {
+      let clip = self.local_clip();
+      let w = source_rect.width();
+      let h = source_rect.height();
+
+      if w <= 0 || h <= 0 {
+         return;
+      }
+
+      let angle = angle + quarter_turns(flags) as f32 * consts::FRAC_PI_2;
+      let (sin, cos) = angle.sin_cos();
+
+      // Bounding box of the rotated source rect
+      let mut min_x = f32::MAX;
+      let mut max_x = f32::MIN;
+      let mut min_y = f32::MAX;
+      let mut max_y = f32::MIN;
+
+      for &(cx, cy) in [(0.0, 0.0), (w as f32, 0.0), (0.0, h as f32), (w as f32, h as f32)].iter() {
+         let ox = cx - pivot_x;
+         let oy = cy - pivot_y;
+         let rx = x as f32 + ox * cos - oy * sin;
+         let ry = y as f32 + ox * sin + oy * cos;
+
+         min_x = min_x.min(rx);
+         max_x = max_x.max(rx);
+         min_y = min_y.min(ry);
+         max_y = max_y.max(ry);
+      }
+
+      let top = cmp::max(min_y.floor() as i32, clip.top);
+      let bottom = cmp::min(max_y.ceil() as i32, clip.bottom);
+      let left = cmp::max(min_x.floor() as i32, clip.left);
+      let right = cmp::min(max_x.ceil() as i32, clip.right);
+
+      let table = self.blend.borrow();
+      let source_pixels = source.pixels.borrow();
+      let mut target_pixels = self.target.pixels.borrow_mut();
+
+      for target_y in top..bottom {
+         for target_x in left..right {
+            // Rotate the pixel center back into source space
+            let ox = target_x as f32 + 0.5 - x as f32;
+            let oy = target_y as f32 + 0.5 - y as f32;
+            let sx = (ox * cos + oy * sin + pivot_x).floor() as i32;
+            let sy = (oy * cos - ox * sin + pivot_y).floor() as i32;
+
+            if sx < 0 || sx >= w || sy < 0 || sy >= h {
+               continue;
             }
+
+            let (u, v) = unrotate(sx, sy, w, h, flags & (DRAW_FLIP_H | DRAW_FLIP_V));
+
+            let source_idx = ((source_rect.left + u) + (source_rect.top + v) * source.width as i32) as usize;
+            let target_idx = self.index(target_x, target_y);
+            put(&mut target_pixels[target_idx], source_pixels[source_idx], flags, color, table.as_ref());
+         }
+      }
+   }
+
+   fn nine_slice(&self, dest_rect: Rect, source: &Bitmap, slice: &NineSlice, flags: u32, color: u8) {
+      for (dest, source_rect, fill) in slice.parts(dest_rect) {
+         match fill {
+            SliceFill::Stretch => self.blit_scaled(dest, source, source_rect, flags, color),
+            SliceFill::Tile => {
+               for y in (dest.top..dest.bottom).step_by(source_rect.height() as usize) {
+                  for x in (dest.left..dest.right).step_by(source_rect.width() as usize) {
+                     let w = cmp::min(source_rect.width(), dest.right - x);
+                     let h = cmp::min(source_rect.height(), dest.bottom - y);
+                     self.blit(x, y, source, Rect::new_size(source_rect.left, source_rect.top, w, h), flags, color);
+                  }
+               }
+            },
          }
       }
    }
@@ -205,55 +880,510 @@ impl<'a> Painter for BitmapPainter<'a> {
    fn text(&self, x: i32, y: i32, text: &str, color: u8, font: &Font) {
       let mut x_curr = x; 
       let mut y_curr = y;
+      let mut color_curr = color;
 
-      let chars_per_row = font.bitmap.width / font.char_width as u32;
+      for (_, markup) in parse_markup(text) {
+         let ch = match markup {
+            Markup::Char(ch) => ch,
+            Markup::Color(tag) => {
+               color_curr = self.markup_color(tag, color_curr, color);
+               continue;
+            },
+         };
 
-      for ch in text.chars() {
-         let idx = ch as u32;
-         if idx < 256 {
-            let ch_x = (idx % chars_per_row) as i32;
-            let ch_y = (idx / chars_per_row) as i32;
+         let (dx, dy) = self.char(x_curr, y_curr, ch, color_curr, font);
+         x_curr += dx;
+         y_curr += dy;
 
-            match ch {
-               ' ' => x_curr += font.char_width,
-               '\t' => x_curr += font.char_width,
-               '\n' => {
-                  x_curr = x;
-                  y_curr += font.line_height;
-               },
-               _ => {
-                  self.blit(x_curr, y_curr, &font.bitmap, Rect::new_size(ch_x * font.char_width, ch_y * font.char_height, font.char_width, font.char_height), DRAW_MASK, color);
-                  x_curr += font.char_width;
-               },
+         if ch == '\n' {
+            x_curr = x;
+         }
+      }
+   }
+
+   fn text_layout(&self, text: &str, layout: &Layout, color: u8, font: &Font) {
+      // Tags skipped between lines, like those cut off by an ellipsis, still change the colour
+      let mut color_curr = color;
+      let mut end = 0;
+
+      for line in layout.lines.iter() {
+         let chunk = match text.get(end..line.range.end) {
+            Some(chunk) => chunk,
+            None => continue,
+         };
+
+         let mut x = line.rect.left;
+         for (range, markup) in parse_markup(chunk) {
+            match markup {
+               Markup::Color(tag) => color_curr = self.markup_color(tag, color_curr, color),
+               Markup::Char(ch) if end + range.start >= line.range.start => x += self.char(x, line.rect.top, ch, color_curr, font).0,
+               Markup::Char(_) => {},
             }
          }
+
+         if line.ellipsis {
+            self.text(x, line.rect.top, layout.ellipsis(), color_curr, font);
+         }
+
+         end = line.range.end;
       }
    }
 
    fn char(&self, x: i32, y: i32, ch: char, color: u8, font: &Font) -> (i32, i32) {
-      let mut dx = 0; 
-      let mut dy = 0;
+      if ch == '\n' {
+         return (0, font.line_height);
+      }
+
+      match font.glyph_or_fallback(ch) {
+         Some(glyph) => {
+            if glyph.rect.width() > 0 && glyph.rect.height() > 0 {
+               self.blit(x + glyph.offset_x, y + glyph.offset_y, &font.bitmap, glyph.rect, DRAW_MASK, color);
+            }
+
+            (glyph.advance, 0)
+         },
+         None => (0, 0),
+      }
+   }
+}
 
-      let chars_per_row = font.bitmap.width / font.char_width as u32;
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use palette::dawn_bringer as pal;
+   use std::env;
+   use std::f32::consts;
+   use std::fs;
 
-      let idx = ch as u32;
-      if idx < 256 {
-         let ch_x = (idx % chars_per_row) as i32;
-         let ch_y = (idx / chars_per_row) as i32;
+   fn snapshot() -> Snapshot {
+      Snapshot::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots"), pal::create_palette())
+   }
 
-         match ch {
-            ' ' => dx += font.char_width,
-            '\t' => dx += font.char_width,
-            '\n' => {
-               dy += font.line_height;
-            },
-            _ => {
-               self.blit(x, y, &font.bitmap, Rect::new_size(ch_x * font.char_width, ch_y * font.char_height, font.char_width, font.char_height), DRAW_MASK, color);
-               dx += font.char_width;
-            },
+   fn checker(w: u32, h: u32) -> Bitmap {
+      let bitmap = Bitmap::new(w, h);
+
+      {
+         let mut pixels = bitmap.pixels.borrow_mut();
+         for y in 0..h {
+            for x in 0..w {
+               pixels[(x + y * w) as usize] = if (x + y) % 2 == 0 { pal::ROYAL_BLUE } else if x == 0 { pal::TRANSPARENT } else { pal::MANDY };
+            }
          }
       }
 
-      (dx, dy)
+      bitmap
    }
-}
\ No newline at end of file
+
+   fn gradient(w: u32, h: u32) -> Vec<u8> {
+      let mut rgba = Vec::new();
+      for _ in 0..h {
+         for x in 0..w {
+            let v = (x * 255 / (w - 1)) as u8;
+            rgba.extend_from_slice(&[v, v, v, 255]);
+         }
+      }
+      rgba
+   }
+
+   #[test]
+   fn save_png() {
+      let dir = env::temp_dir().join(format!("tiny-save-{}", std::process::id()));
+      fs::create_dir_all(&dir).unwrap();
+
+      let palette = pal::create_palette();
+      let bitmap = checker(5, 3);
+      let expected = bitmap.to_rgba(&palette);
+
+      bitmap.save(&dir.join("rgba.png"), &palette).unwrap();
+      bitmap.save_indexed(&dir.join("indexed.png"), &palette).unwrap();
+
+      for name in ["rgba.png", "indexed.png"].iter() {
+         let img = image::open(dir.join(name)).unwrap().to_rgba();
+         assert_eq!(img.dimensions(), (5, 3));
+         assert!(img.into_raw() == expected, "{}", name);
+      }
+
+      // Indices survive a round trip through the indexed file
+      let mut ctx_palette = pal::create_palette();
+      let loaded = image::open(dir.join("indexed.png")).unwrap().to_rgba();
+      let reloaded = extend_palette(&mut ctx_palette, &loaded.into_raw(), 5, 3).unwrap();
+      assert!(*reloaded.pixels.borrow() == *bitmap.pixels.borrow());
+
+      assert!(bitmap.save(&dir.join("missing").join("rgba.png"), &palette).is_err());
+      fs::remove_dir_all(&dir).unwrap();
+   }
+
+   #[test]
+   fn quantize_nearest() {
+      let palette = pal::create_palette();
+      let rgba = [250, 250, 250, 255,  2, 1, 3, 255,  170, 50, 52, 200,  9, 9, 9, 0];
+
+      let bitmap = Bitmap::from_rgba(&rgba, 4, 1, &palette, Dither::None);
+      assert_eq!(bitmap.pixel(0, 0), pal::WHITE);
+      assert_eq!(bitmap.pixel(1, 0), pal::BLACK);
+      assert_eq!(bitmap.pixel(2, 0), pal::BROWN);
+      assert_eq!(bitmap.pixel(3, 0), TRANSPARENT);
+   }
+
+   #[test]
+   fn quantize_dithered() {
+      let mut palette = pal::create_palette();
+      palette.colors.truncate(2);
+      palette.add_color(Color::new(255, 255, 255, 255));
+
+      let rgba = gradient(32, 8);
+      let plain = Bitmap::from_rgba(&rgba, 32, 8, &palette, Dither::None);
+      let ordered = Bitmap::from_rgba(&rgba, 32, 8, &palette, Dither::Ordered);
+      let diffused = Bitmap::from_rgba(&rgba, 32, 8, &palette, Dither::FloydSteinberg);
+
+      // Count white pixels, dithering should keep the average brightness of the gradient
+      let whites = |b: &Bitmap| b.pixels.borrow().iter().filter(|p| **p == 2).count() as i32;
+      assert_eq!(whites(&plain), 16 * 8);
+      assert!((whites(&diffused) - 16 * 8).abs() <= 8);
+      assert!((whites(&ordered) - 16 * 8).abs() <= 16);
+
+      // Mid grey should not come out as a solid block when dithering
+      assert!(diffused.pixel(15, 0) != diffused.pixel(15, 1) || diffused.pixel(15, 0) != diffused.pixel(16, 0));
+      assert!(ordered.pixel(14, 0) != ordered.pixel(14, 1) || ordered.pixel(14, 0) != ordered.pixel(15, 0));
+   }
+
+   #[test]
+   fn extend_palette_limit() {
+      let mut palette = pal::create_palette();
+      let rgba = gradient(256, 1);
+
+      assert!(extend_palette(&mut palette, &rgba, 256, 1).is_err());
+      assert_eq!(palette.colors.len(), 33);
+
+      let bitmap = extend_palette(&mut palette, &rgba[..64 * 4], 64, 1).unwrap();
+      assert_eq!(bitmap.pixel(0, 0), pal::BLACK);
+      assert_eq!(palette.colors.len(), 33 + 63);
+      assert!(palette.colors[bitmap.pixel(63, 0) as usize] == Color::new(63, 63, 63, 255));
+   }
+
+   #[test]
+   fn paint_lines() {
+      snapshot().paint("lines", 32, 32, |p| {
+         p.clear(pal::BLACK);
+         p.line(1, 1, 30, 1, pal::WHITE);
+         p.line(1, 3, 1, 30, pal::WHITE);
+         p.line(3, 3, 30, 30, pal::CORNFLOWER);
+         p.line(30, 3, 3, 12, pal::MANDY);
+         p.line(10, 30, 14, 5, pal::RAIN_FOREST);
+      }).unwrap();
+   }
+
+   #[test]
+   fn paint_rects() {
+      snapshot().paint("rects", 32, 32, |p| {
+         p.clear(pal::BLACK);
+         p.rect_fill(Rect::new_size(2, 2, 12, 8), pal::CORNFLOWER);
+         p.rect_stroke(Rect::new_size(16, 2, 12, 8), pal::WHITE);
+         p.rect_fill(Rect::new_size(4, 14, 24, 14), pal::VALHALLA);
+         p.rect_stroke(Rect::new_size(4, 14, 24, 14), pal::MANDY);
+      }).unwrap();
+   }
+
+   #[test]
+   fn paint_blit() {
+      let source = checker(6, 5);
+
+      snapshot().paint("blit", 32, 16, |p| {
+         p.clear(pal::BLACK);
+         p.blit(1, 1, &source, Rect::new_size(0, 0, 6, 5), 0, 0);
+         p.blit(9, 1, &source, Rect::new_size(0, 0, 6, 5), DRAW_MASK, pal::WHITE);
+         p.blit(17, 1, &source, Rect::new_size(1, 1, 4, 3), 0, 0);
+      }).unwrap();
+   }
+
+   #[test]
+   fn paint_blit_transformed() {
+      let source = checker(6, 4);
+      let full = Rect::new_size(0, 0, 6, 4);
+
+      snapshot().paint("blit_transformed", 64, 40, |p| {
+         p.clear(pal::BLACK);
+         p.blit(1, 1, &source, full, DRAW_FLIP_H, 0);
+         p.blit(9, 1, &source, full, DRAW_FLIP_V, 0);
+         p.blit(17, 1, &source, full, DRAW_ROTATE_90, 0);
+         p.blit(23, 1, &source, full, DRAW_ROTATE_180, 0);
+         p.blit(31, 1, &source, full, DRAW_ROTATE_270 | DRAW_FLIP_H, 0);
+         p.blit_scaled(Rect::new_size(1, 9, 12, 8), &source, full, 0, 0);
+         p.blit_scaled(Rect::new_size(15, 9, 9, 6), &source, full, DRAW_MASK, pal::WHITE);
+         p.blit_scaled(Rect::new_size(26, 9, 3, 2), &source, full, 0, 0);
+         p.blit_rotated(12, 28, &source, full, 3.0, 2.0, consts::FRAC_PI_4, 0, 0);
+         p.blit_rotated(32, 28, &source, full, 0.0, 0.0, 1.0, DRAW_FLIP_V, 0);
+         p.blit_rotated(52, 28, &source, full, 3.0, 2.0, 0.0, DRAW_ROTATE_90, 0);
+      }).unwrap();
+   }
+
+   #[test]
+   fn blit_transforms_match() {
+      let source = checker(5, 3);
+      let full = Rect::new_size(0, 0, 5, 3);
+
+      // Two horizontal flips and a half turn undo each other
+      let mut a = Bitmap::new(8, 8);
+      let mut b = Bitmap::new(8, 8);
+      BitmapPainter::new(&mut a).blit(1, 1, &source, full, DRAW_ROTATE_180, 0);
+      BitmapPainter::new(&mut b).blit(1, 1, &source, full, DRAW_FLIP_H | DRAW_FLIP_V, 0);
+      assert!(a.pixels == b.pixels);
+
+      // A quarter turn through blit_rotated lands on the same pixels as the flag
+      let mut c = Bitmap::new(8, 8);
+      let mut d = Bitmap::new(8, 8);
+      BitmapPainter::new(&mut c).blit(1, 1, &source, full, DRAW_ROTATE_90, 0);
+      BitmapPainter::new(&mut d).blit_rotated(1, 1, &source, full, 0.0, 3.0, consts::FRAC_PI_2, 0, 0);
+      assert!(c.pixels == d.pixels);
+   }
+
+   #[test]
+   fn paint_blended() {
+      let palette = pal::create_palette();
+      let shadow = Rc::new(BlendTable::darken(&palette, 1));
+      let glass = Rc::new(BlendTable::mix(&palette, 0.5));
+      let source = checker(8, 8);
+
+      snapshot().paint("blended", 48, 24, |p| {
+         p.clear(pal::BLACK);
+         p.rect_fill(Rect::new_size(0, 0, 24, 24), pal::CORNFLOWER);
+         p.rect_fill(Rect::new_size(24, 0, 24, 24), pal::WHITE);
+
+         p.blend(Some(shadow.clone()));
+         p.ellipse_fill(12, 18, 8, 3, pal::BLACK);
+         p.blit(30, 2, &source, Rect::new_size(0, 0, 8, 8), DRAW_MASK, pal::BLACK);
+
+         p.blend(Some(glass.clone()));
+         p.rect_fill(Rect::new_size(16, 4, 16, 8), pal::MANDY);
+         p.circle_stroke(40, 16, 5, pal::ROYAL_BLUE);
+
+         p.blend(None);
+         p.pixel(0, 0, pal::MANDY);
+      }).unwrap();
+   }
+
+   #[test]
+   fn rect_fill_clipped() {
+      let mut bitmap = Bitmap::new(8, 8);
+      {
+         let p = BitmapPainter::new(&mut bitmap);
+         p.rect_fill(Rect::new_size(-4, -4, 20, 20), pal::WHITE);
+         p.clip(Some(Rect::new_size(2, 2, 2, 2)));
+         p.rect_fill(Rect::new_size(0, 0, 8, 8), pal::MANDY);
+      }
+
+      assert_eq!(bitmap.pixel(7, 7), pal::WHITE);
+      assert_eq!(bitmap.pixel(1, 2), pal::WHITE);
+      assert_eq!(bitmap.pixel(2, 2), pal::MANDY);
+      assert_eq!(bitmap.pixel(3, 3), pal::MANDY);
+      assert_eq!(bitmap.pixel(4, 3), pal::WHITE);
+   }
+
+   #[test]
+   fn paint_text() {
+      let font = default_font::font_4x7();
+
+      snapshot().paint("text", 64, 32, |p| {
+         p.clear(pal::BLACK);
+         p.text(1, 1, "TINY RTS", pal::WHITE, &font);
+         p.text(1, 10, "0123\nabc!", pal::CORNFLOWER, &font);
+      }).unwrap();
+   }
+
+   #[test]
+   fn paint_text_layout() {
+      let font = default_font::font_4x7();
+      let text = "Barracks train footmen and archers";
+      let text_box = TextBox::new(Rect::new_size(2, 2, 44, 24)).with_align(Align::Center).with_ellipsis(true);
+
+      snapshot().paint("text_layout", 48, 28, |p| {
+         p.clear(pal::BLACK);
+         p.rect_stroke(text_box.rect, pal::VALHALLA);
+         p.text_layout(text, &font.layout(text, &text_box), pal::WHITE, &font);
+      }).unwrap();
+   }
+
+   #[test]
+   fn text_layout_other_text() {
+      let font = default_font::font_4x7();
+      let layout = font.layout("Barracks", &TextBox::new(Rect::new_size(0, 0, 40, 8)));
+
+      // Lines that don't fit in the text given are skipped instead of panicking
+      let mut canvas = Bitmap::new(40, 8);
+      BitmapPainter::new(&mut canvas).text_layout("Bar\u{20ac}", &layout, pal::WHITE, &font);
+      BitmapPainter::new(&mut canvas).text_layout("Bar", &layout, pal::WHITE, &font);
+      assert!(canvas.pixels.borrow().iter().all(|&p| p == 0));
+   }
+
+   #[test]
+   fn paint_text_markup() {
+      let font = default_font::font_4x7();
+      let text = "{mandy}Error:{} no {19}gold{} {{left}";
+      let text_box = TextBox::new(Rect::new_size(2, 12, 44, 14));
+
+      snapshot().paint("text_markup", 48, 28, |p| {
+         p.clear(pal::BLACK);
+         p.text(2, 2, text, pal::WHITE, &font);
+         p.text_layout(text, &font.layout(text, &text_box), pal::WHITE, &font);
+      }).unwrap();
+
+      // Without a palette names are ignored and indices still apply
+      let mut canvas = Bitmap::new(8, 7);
+      BitmapPainter::new(&mut canvas).text(0, 0, "{Mandy}|{19}|", pal::WHITE, &font);
+      assert!(canvas.pixels.borrow().contains(&pal::WHITE) && canvas.pixels.borrow().contains(&pal::CORNFLOWER));
+      assert!(!canvas.pixels.borrow().contains(&pal::MANDY));
+   }
+
+   #[test]
+   fn paint_nine_slice() {
+      // A 6x6 frame with a 2 pixel border, the edges have a pattern that shows tiling
+      let mut skin = Bitmap::new(6, 6);
+      {
+         let p = BitmapPainter::new(&mut skin);
+         p.clear(pal::ROYAL_BLUE);
+         p.rect_fill(Rect::new_size(0, 0, 6, 2), pal::WHITE);
+         p.rect_fill(Rect::new_size(0, 4, 6, 2), pal::VALHALLA);
+         p.pixel(0, 0, pal::TRANSPARENT);
+         p.pixel(2, 1, pal::MANDY);
+         p.pixel(2, 4, pal::MANDY);
+         p.rect_fill(Rect::new_size(2, 2, 2, 2), pal::CORNFLOWER);
+      }
+
+      let slice = NineSlice::uniform(Rect::new_size(0, 0, 6, 6), 2);
+
+      snapshot().paint("nine_slice", 48, 28, |p| {
+         p.clear(pal::BLACK);
+         p.nine_slice(Rect::new_size(2, 2, 20, 11), &skin, &slice, 0, 0);
+         p.nine_slice(Rect::new_size(26, 2, 20, 11), &skin, &slice.with_edges(SliceFill::Tile).with_center(None), 0, 0);
+         p.nine_slice(Rect::new_size(2, 16, 3, 3), &skin, &slice, 0, 0);
+         p.nine_slice(Rect::new_size(26, 16, 20, 10), &skin, &slice.with_center(Some(SliceFill::Tile)), DRAW_MASK, pal::MANDY);
+      }).unwrap();
+   }
+
+   #[test]
+   fn translation() {
+      let font = default_font::font_4x7();
+      let source = checker(6, 5);
+
+      let scene = |p: &Painter, x: i32, y: i32| {
+         p.clip(Some(Rect::new_size(x + 1, y + 1, 30, 22)));
+         p.rect_fill(Rect::new_size(x, y, 12, 6), pal::VALHALLA);
+         p.rect_stroke(Rect::new_size(x + 2, y + 2, 8, 8), pal::WHITE);
+         p.pixel(x + 1, y + 1, pal::MANDY);
+         p.circle_fill(x + 20, y + 6, 5, pal::CORNFLOWER);
+         p.ellipse_stroke(x + 20, y + 6, 7, 3, pal::WHITE);
+         p.polygon_fill(&[(x, y + 12), (x + 10, y + 14), (x + 4, y + 24)], pal::RAIN_FOREST);
+         p.triangle_stroke(x + 12, y + 12, x + 20, y + 14, x + 14, y + 22, pal::ROPE);
+         p.blit(x + 24, y + 10, &source, Rect::new_size(0, 0, 6, 5), DRAW_FLIP_H, 0);
+         p.blit_scaled(Rect::new_size(x + 24, y + 16, 12, 4), &source, Rect::new_size(0, 0, 6, 5), 0, 0);
+         p.blit_rotated(x + 4, y + 28, &source, Rect::new_size(0, 0, 6, 5), 3.0, 2.5, 0.6, 0, 0);
+         p.text(x + 2, y + 16, "Hi", pal::GOLDEN_FIZZ, &font);
+         p.clip(None);
+      };
+
+      let mut expected = Bitmap::new(48, 40);
+      scene(&BitmapPainter::new(&mut expected), 9, 5);
+
+      let mut actual = Bitmap::new(48, 40);
+      {
+         let p = BitmapPainter::new(&mut actual);
+         p.push_translation(4, 2);
+         p.push_translation(5, 3);
+         assert_eq!(p.translation(), (9, 5));
+         scene(&p, 0, 0);
+         p.pop_translation();
+         assert_eq!(p.translation(), (4, 2));
+         p.pop_translation();
+         p.pop_translation();
+         assert_eq!(p.translation(), (0, 0));
+      }
+
+      assert!(*actual.pixels.borrow() == *expected.pixels.borrow());
+      assert!(actual.pixels.borrow().iter().any(|&p| p == pal::GOLDEN_FIZZ));
+   }
+
+   #[test]
+   fn clip_stack() {
+      let mut canvas = Bitmap::new(16, 16);
+      {
+         let p = BitmapPainter::new(&mut canvas);
+
+         // A window with a panel inside it, each clipping in its own coordinates
+         p.push_translation(2, 2);
+         p.push_clip(Rect::new_size(0, 0, 10, 10));
+         p.push_translation(6, 6);
+         p.push_clip(Rect::new_size(-2, -2, 20, 20));
+
+         p.clip(None);
+         p.rect_fill(Rect::new_size(-10, -10, 40, 40), pal::WHITE);
+         p.clip(Some(Rect::new_size(0, 0, 1, 1)));
+         p.rect_fill(Rect::new_size(-10, -10, 40, 40), pal::TRANSPARENT);
+
+         p.pop_clip();
+         p.pop_translation();
+         p.line(0, 0, 0, 15, pal::MANDY);
+
+         p.pop_clip();
+         p.pop_clip();
+         p.pop_translation();
+         p.pixel(15, 15, pal::CORNFLOWER);
+      }
+
+      let white = canvas.pixels.borrow().iter().filter(|&&p| p == pal::WHITE).count();
+      assert_eq!(white, 6 * 6 - 1);
+      assert_eq!((canvas.pixel(5, 5), canvas.pixel(6, 6), canvas.pixel(8, 8), canvas.pixel(11, 11), canvas.pixel(12, 12)), (0, pal::WHITE, 0, pal::WHITE, 0));
+      assert_eq!((canvas.pixel(2, 2), canvas.pixel(2, 11), canvas.pixel(2, 12)), (pal::MANDY, pal::MANDY, 0));
+      assert_eq!(canvas.pixel(15, 15), pal::CORNFLOWER);
+
+      assert!(Rect::new(0, 0, 10, 10).intersect(Rect::new(4, 6, 20, 8)) == Rect::new(4, 6, 10, 8));
+   }
+
+   #[test]
+   fn paint_shapes() {
+      snapshot().paint("shapes", 64, 48, |p| {
+         p.clear(pal::BLACK);
+         p.circle_fill(10, 10, 8, pal::ROYAL_BLUE);
+         p.circle_stroke(10, 10, 8, pal::WHITE);
+         p.circle_stroke(30, 10, 0, pal::WHITE);
+         p.circle_stroke(30, 10, 3, pal::MANDY);
+         p.ellipse_fill(50, 10, 12, 5, pal::RAIN_FOREST);
+         p.ellipse_stroke(50, 10, 12, 5, pal::WHITE);
+         p.ellipse_stroke(50, 20, 6, 0, pal::MANDY);
+         p.triangle_fill(2, 44, 14, 24, 26, 40, pal::CORNFLOWER);
+         p.triangle_stroke(2, 44, 14, 24, 26, 40, pal::WHITE);
+         p.polygon_fill(&[(32, 26), (62, 26), (62, 46), (47, 34), (32, 46)], pal::MANDY);
+         p.polygon_stroke(&[(32, 26), (62, 26), (62, 46), (47, 34), (32, 46)], pal::WHITE);
+      }).unwrap();
+   }
+
+   #[test]
+   fn paint_shapes_clipped() {
+      snapshot().paint("shapes_clipped", 32, 32, |p| {
+         p.clear(pal::BLACK);
+         p.circle_fill(-2, -2, 10, pal::ROYAL_BLUE);
+         p.circle_stroke(30, 30, 6, pal::WHITE);
+         p.ellipse_fill(16, 34, 20, 6, pal::RAIN_FOREST);
+         p.clip(Some(Rect::new_size(8, 8, 16, 16)));
+         p.circle_fill(16, 16, 10, pal::MANDY);
+         p.triangle_fill(0, 0, 31, 4, 4, 31, pal::CORNFLOWER);
+         p.polygon_stroke(&[(8, 8), (23, 8), (23, 23), (8, 23)], pal::WHITE);
+      }).unwrap();
+   }
+
+   #[test]
+   fn paint_clipped() {
+      let font = default_font::font_4x7();
+      let source = checker(8, 8);
+
+      snapshot().paint("clipped", 32, 32, |p| {
+         p.clear(pal::BLACK);
+         p.clip(Some(Rect::new_size(4, 4, 20, 20)));
+         p.line(0, 0, 31, 31, pal::WHITE);
+         p.line(0, 10, 31, 10, pal::MANDY);
+         p.blit(18, 18, &source, Rect::new_size(0, 0, 8, 8), 0, 0);
+         p.text(2, 6, "CLIP", pal::CORNFLOWER, &font);
+         p.clip(None);
+         p.pixel(31, 0, pal::WHITE);
+      }).unwrap();
+   }
+}
@@ -0,0 +1,188 @@
+use super::*;
+
+/// Lookup table that combines a target and a source palette index into a new index.
+///
+/// Tables are built once from a `Palette`, every combined colour is mapped back to the
+/// nearest palette entry. Set one on a `Painter` with `blend` to get translucent drawing.
+#[derive(Clone)]
+pub struct BlendTable {
+   table: Vec<u8>,
+}
+
+impl BlendTable {
+   /// Builds a table from `f(target, source)`. A transparent source leaves the target untouched.
+   pub fn new<F>(palette: &Palette, f: F) -> BlendTable
+      where F: Fn(Color, Color) -> Color
+   {
+      let mut table = vec![0u8; 256 * 256];
+
+      for target in 0..256 {
+         for source in 0..256 {
+            table[target * 256 + source] = if source == 0 {
+               target as u8
+            } else if target >= palette.colors.len() || source >= palette.colors.len() {
+               source as u8
+            } else {
+               palette.nearest(f(palette.colors[target], palette.colors[source]))
+            };
+         }
+      }
+
+      BlendTable {
+         table: table,
+      }
+   }
+
+   /// Mixes `amount` of the source with the target, `0.5` gives a 50% mix.
+   pub fn mix(palette: &Palette, amount: f32) -> BlendTable {
+      let amount = amount.max(0.0).min(1.0);
+
+      BlendTable::new(palette, |t, s| {
+         let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+         Color::new(lerp(t.red(), s.red()), lerp(t.green(), s.green()), lerp(t.blue(), s.blue()), 255)
+      })
+   }
+
+   pub fn additive(palette: &Palette) -> BlendTable {
+      BlendTable::new(palette, |t, s| {
+         Color::new(t.red().saturating_add(s.red()), t.green().saturating_add(s.green()), t.blue().saturating_add(s.blue()), 255)
+      })
+   }
+
+   pub fn multiply(palette: &Palette) -> BlendTable {
+      BlendTable::new(palette, |t, s| {
+         let mul = |a: u8, b: u8| ((a as u32 * b as u32 + 127) / 255) as u8;
+         Color::new(mul(t.red(), s.red()), mul(t.green(), s.green()), mul(t.blue(), s.blue()), 255)
+      })
+   }
+
+   /// Darkens the target `steps` times regardless of the source colour, useful for shadows.
+   /// Each step moves to the closest palette entry that is darker than the current one.
+   pub fn darken(palette: &Palette, steps: u32) -> BlendTable {
+      let mut darker: Vec<u8> = Vec::with_capacity(palette.colors.len());
+
+      for (idx, color) in palette.colors.iter().enumerate() {
+         let luma = luminance(*color);
+         let target = Color::new((color.red() as u32 * 3 / 4) as u8, (color.green() as u32 * 3 / 4) as u8, (color.blue() as u32 * 3 / 4) as u8, 255);
+
+         let mut best = idx as u8;
+         let mut best_distance = u32::max_value();
+
+         for (candidate, c) in palette.colors.iter().enumerate() {
+            if c.alpha() == 0 || luminance(*c) >= luma {
+               continue;
+            }
+
+            let distance = distance(target, *c);
+            if distance < best_distance {
+               best = candidate as u8;
+               best_distance = distance;
+            }
+         }
+
+         darker.push(best);
+      }
+
+      // Start out with a table that simply writes the source
+      let mut table = BlendTable {
+         table: (0..256 * 256).map(|i| if i & 0xff == 0 { (i >> 8) as u8 } else { (i & 0xff) as u8 }).collect(),
+      };
+
+      for target in 0..darker.len() {
+         let mut result = target as u8;
+         for _ in 0..steps {
+            result = darker[result as usize];
+         }
+
+         for source in 1..256 {
+            table.table[target * 256 + source] = result;
+         }
+      }
+
+      table
+   }
+
+   #[inline]
+   pub fn get(&self, target: u8, source: u8) -> u8 {
+      self.table[((target as usize) << 8) | source as usize]
+   }
+}
+
+
+impl Palette {
+   /// Finds the palette entry closest to `color`, transparent entries are never picked.
+   pub fn nearest(&self, color: Color) -> u8 {
+      let mut best = 0;
+      let mut best_distance = u32::max_value();
+
+      for (idx, c) in self.colors.iter().enumerate() {
+         if c.alpha() == 0 {
+            continue;
+         }
+
+         let distance = distance(color, *c);
+         if distance < best_distance {
+            best = idx as u8;
+            best_distance = distance;
+
+            if distance == 0 {
+               break;
+            }
+         }
+      }
+
+      best
+   }
+}
+
+
+/// Perceptually weighted luminance in the range 0-255000.
+#[inline]
+fn luminance(color: Color) -> u32 {
+   color.red() as u32 * 299 + color.green() as u32 * 587 + color.blue() as u32 * 114
+}
+
+/// Squared distance between two colours, ignoring alpha.
+#[inline]
+fn distance(a: Color, b: Color) -> u32 {
+   let dr = a.red() as i32 - b.red() as i32;
+   let dg = a.green() as i32 - b.green() as i32;
+   let db = a.blue() as i32 - b.blue() as i32;
+   (dr * dr + dg * dg + db * db) as u32
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use palette::dawn_bringer as pal;
+
+   #[test]
+   fn nearest_colour() {
+      let palette = pal::create_palette();
+      assert_eq!(palette.nearest(Color::new(250, 250, 250, 255)), pal::WHITE);
+      assert_eq!(palette.nearest(Color::new(3, 2, 1, 255)), pal::BLACK);
+      assert_eq!(palette.nearest(Color::new(0, 0, 0, 0)), pal::BLACK);
+   }
+
+   #[test]
+   fn tables() {
+      let palette = pal::create_palette();
+
+      let mix = BlendTable::mix(&palette, 0.5);
+      assert_eq!(mix.get(pal::WHITE, pal::WHITE), pal::WHITE);
+      assert_eq!(mix.get(pal::BLACK, TRANSPARENT), pal::BLACK);
+
+      let multiply = BlendTable::multiply(&palette);
+      assert_eq!(multiply.get(pal::MANDY, pal::WHITE), pal::MANDY);
+      assert_eq!(multiply.get(pal::MANDY, pal::BLACK), pal::BLACK);
+
+      let additive = BlendTable::additive(&palette);
+      assert_eq!(additive.get(pal::BLACK, pal::CORNFLOWER), pal::CORNFLOWER);
+
+      let darken = BlendTable::darken(&palette, 2);
+      assert_eq!(darken.get(pal::BLACK, pal::WHITE), pal::BLACK);
+      assert!(luminance(palette.colors[darken.get(pal::WHITE, pal::MANDY) as usize]) < luminance(palette.colors[pal::WHITE as usize]));
+      assert_eq!(BlendTable::darken(&palette, 0).get(pal::MANDY, pal::WHITE), pal::MANDY);
+   }
+}
@@ -0,0 +1,107 @@
+use super::*;
+
+use std::cell::{Cell, Ref, RefCell};
+
+/// An offscreen bitmap for layers that seldom change, like terrain. Paint it once and
+/// blit its `bitmap` every frame, repainting only after `invalidate`.
+///
+/// Painting happens through a fresh `BitmapPainter`, so it works while another painter is
+/// in use, except for one drawing this same canvas.
+pub struct Canvas {
+   bitmap: RefCell<Bitmap>,
+   dirty: Cell<bool>,
+}
+
+impl Canvas {
+   /// A transparent canvas that still needs painting.
+   pub fn new(width: u32, height: u32) -> Canvas {
+      Canvas {
+         bitmap: RefCell::new(Bitmap::new(width, height)),
+         dirty: Cell::new(true),
+      }
+   }
+
+   pub fn size(&self) -> (u32, u32) {
+      let bitmap = self.bitmap.borrow();
+      (bitmap.width, bitmap.height)
+   }
+
+   /// The whole canvas, as the source rect for blitting it.
+   pub fn rect(&self) -> Rect {
+      let (w, h) = self.size();
+      Rect::new_size(0, 0, w as i32, h as i32)
+   }
+
+   pub fn bitmap(&self) -> Ref<Bitmap> {
+      self.bitmap.borrow()
+   }
+
+   /// Whether the canvas was invalidated, or never painted, since it was last painted.
+   pub fn dirty(&self) -> bool {
+      self.dirty.get()
+   }
+
+   pub fn invalidate(&self) {
+      self.dirty.set(true);
+   }
+
+   /// Draws on top of what the canvas holds. Set a palette on the painter to use colour
+   /// names in text markup.
+   pub fn paint<F>(&self, f: F) where F: FnOnce(&Painter) {
+      let mut bitmap = self.bitmap.borrow_mut();
+      f(&BitmapPainter::new(&mut bitmap));
+      self.dirty.set(false);
+   }
+
+   /// Paints the canvas only if it is dirty, returns whether it did.
+   pub fn paint_if_dirty<F>(&self, f: F) -> bool where F: FnOnce(&Painter) {
+      if self.dirty() {
+         self.paint(f);
+         true
+      } else {
+         false
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use palette::dawn_bringer as pal;
+
+   #[test]
+   fn layers() {
+      let terrain = Canvas::new(8, 8);
+      let mut painted = 0;
+
+      for _ in 0..3 {
+         terrain.paint_if_dirty(|p| {
+            painted += 1;
+            p.clear(pal::RAIN_FOREST);
+            p.push_translation(4, 4);
+            p.rect_fill(Rect::new_size(0, 0, 2, 2), pal::ROPE);
+            p.pop_translation();
+         });
+      }
+
+      assert_eq!(painted, 1);
+      assert!(!terrain.dirty());
+
+      // Compose the layer into a frame while painting it, translated by the frame
+      let mut frame = Bitmap::new(16, 16);
+      {
+         let p = BitmapPainter::new(&mut frame);
+         p.push_translation(6, 2);
+         p.blit(0, 0, &terrain.bitmap(), terrain.rect(), 0, 0);
+
+         terrain.invalidate();
+         terrain.paint(|p| p.pixel(0, 0, pal::MANDY));
+         p.blit(0, 10, &terrain.bitmap(), Rect::new_size(0, 0, 1, 1), 0, 0);
+         p.pop_translation();
+      }
+
+      assert_eq!((frame.pixel(6, 2), frame.pixel(10, 6), frame.pixel(14, 10)), (pal::RAIN_FOREST, pal::ROPE, 0));
+      assert_eq!((frame.pixel(6, 12), frame.pixel(7, 12)), (pal::MANDY, 0));
+   }
+}
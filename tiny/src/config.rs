@@ -0,0 +1,299 @@
+use super::*;
+
+/// Controls how often the main loop steps and paints.
+///
+/// `step` runs `step_rate` times per second of real time no matter how fast frames are
+/// painted. A slow frame is caught up on with up to `max_steps` steps, any time beyond
+/// that is dropped. `paint` runs once per frame, at most `frame_rate` times per second
+/// or as fast as the window allows when it is `None`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timestep {
+   pub step_rate: u32,
+   pub max_steps: u32,
+   pub frame_rate: Option<u32>,
+}
+
+impl Timestep {
+   /// Length of a single step in seconds.
+   pub fn step_length(&self) -> f64 {
+      1.0 / cmp::max(self.step_rate, 1) as f64
+   }
+}
+
+impl Default for Timestep {
+   fn default() -> Timestep {
+      Timestep {
+         step_rate: 30,
+         max_steps: 5,
+         frame_rate: Some(60),
+      }
+   }
+}
+
+
+/// How held keys repeat, see `Context::key_repeated`. A key repeats `delay` seconds after
+/// it was pressed and then `rate` times per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyRepeat {
+   pub delay: f32,
+   pub rate: f32,
+}
+
+impl Default for KeyRepeat {
+   fn default() -> KeyRepeat {
+      KeyRepeat {
+         delay: 0.4,
+         rate: 15.0,
+      }
+   }
+}
+
+
+/// How the canvas is scaled up when the window is larger than it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scaling {
+   /// Only whole multiples of the canvas size, keeps every pixel the same size.
+   Integer,
+   /// As large as fits while keeping the aspect ratio.
+   Fit,
+}
+
+/// How much Tiny itself prints, each level includes the ones before it.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+   Off,
+   Error,
+   Info,
+   Debug,
+}
+
+
+/// Everything needed to start the main loop, built with the `with_*` methods.
+///
+/// ```no_run
+/// # struct App;
+/// # impl tiny::Application for App {
+/// #    fn new(_ctx: &mut tiny::Context) -> Result<App, String> { Ok(App) }
+/// #    fn paint(&self, _ctx: &tiny::Context, _painter: &tiny::Painter, _alpha: f32) {}
+/// # }
+/// let config = tiny::Config::new("Tiny RTS", 320, 200)
+///    .with_scale(3)
+///    .with_resizable(true)
+///    .with_vsync(true)
+///    .with_frame_rate(None);
+///
+/// tiny::run::<App>(config).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Config {
+   title: String,
+   width: u32,
+   height: u32,
+   scale: u32,
+   timestep: Timestep,
+   key_repeat: KeyRepeat,
+   resizable: bool,
+   scaling: Scaling,
+   vsync: bool,
+   palette: Option<Palette>,
+   headless: bool,
+   log_level: LogLevel,
+   input_recording: Option<PathBuf>,
+   input_playback: Option<PathBuf>,
+}
+
+impl Config {
+   /// A fixed size window showing a `width` x `height` canvas at scale 1.
+   /// Headless when Tiny is built with the `headless` feature.
+   pub fn new(title: &str, width: u32, height: u32) -> Config {
+      Config {
+         title: String::from(title),
+         width: width,
+         height: height,
+         scale: 1,
+         timestep: Timestep::default(),
+         key_repeat: KeyRepeat::default(),
+         resizable: false,
+         scaling: Scaling::Integer,
+         vsync: false,
+         palette: None,
+         headless: cfg!(feature = "headless"),
+         log_level: LogLevel::Info,
+         input_recording: None,
+         input_playback: None,
+      }
+   }
+
+   pub fn with_title(mut self, title: &str) -> Config {
+      self.title = String::from(title);
+      self
+   }
+
+   /// Size of the canvas, the window starts out at this size times the scale.
+   pub fn with_size(mut self, width: u32, height: u32) -> Config {
+      self.width = width;
+      self.height = height;
+      self
+   }
+
+   pub fn with_scale(mut self, scale: u32) -> Config {
+      self.scale = cmp::max(scale, 1);
+      self
+   }
+
+   pub fn with_timestep(mut self, timestep: Timestep) -> Config {
+      self.timestep = timestep;
+      self
+   }
+
+   /// Number of fixed steps per second.
+   pub fn with_step_rate(mut self, step_rate: u32) -> Config {
+      self.timestep.step_rate = step_rate;
+      self
+   }
+
+   /// Caps the number of frames painted per second, `None` paints as fast as possible.
+   pub fn with_frame_rate(mut self, frame_rate: Option<u32>) -> Config {
+      self.timestep.frame_rate = frame_rate;
+      self
+   }
+
+   pub fn with_key_repeat(mut self, key_repeat: KeyRepeat) -> Config {
+      self.key_repeat = key_repeat;
+      self
+   }
+
+   pub fn with_resizable(mut self, resizable: bool) -> Config {
+      self.resizable = resizable;
+      self
+   }
+
+   pub fn with_scaling(mut self, scaling: Scaling) -> Config {
+      self.scaling = scaling;
+      self
+   }
+
+   /// Waits for the display when showing a frame. Only the glutin backend supports it, the
+   /// win32 backend never waits.
+   pub fn with_vsync(mut self, vsync: bool) -> Config {
+      self.vsync = vsync;
+      self
+   }
+
+   /// Palette the context starts out with instead of just transparent, black and white.
+   pub fn with_palette(mut self, palette: Palette) -> Config {
+      self.palette = Some(palette);
+      self
+   }
+
+   /// Runs without opening a window, see `Headless` for driving an application by hand.
+   pub fn with_headless(mut self, headless: bool) -> Config {
+      self.headless = headless;
+      self
+   }
+
+   pub fn with_log_level(mut self, log_level: LogLevel) -> Config {
+      self.log_level = log_level;
+      self
+   }
+
+   /// Records the input from the first frame on, see `Context::start_input_recording`.
+   pub fn with_input_recording<P: AsRef<Path>>(mut self, path: Option<P>) -> Config {
+      self.input_recording = path.map(|path| path.as_ref().to_path_buf());
+      self
+   }
+
+   /// Plays back recorded input from the first frame on, see `Context::start_input_playback`.
+   pub fn with_input_playback<P: AsRef<Path>>(mut self, path: Option<P>) -> Config {
+      self.input_playback = path.map(|path| path.as_ref().to_path_buf());
+      self
+   }
+
+   pub fn title(&self) -> &str {
+      &self.title
+   }
+
+   pub fn width(&self) -> u32 {
+      self.width
+   }
+
+   pub fn height(&self) -> u32 {
+      self.height
+   }
+
+   pub fn scale(&self) -> u32 {
+      self.scale
+   }
+
+   pub fn timestep(&self) -> Timestep {
+      self.timestep
+   }
+
+   pub fn key_repeat(&self) -> KeyRepeat {
+      self.key_repeat
+   }
+
+   pub fn resizable(&self) -> bool {
+      self.resizable
+   }
+
+   pub fn scaling(&self) -> Scaling {
+      self.scaling
+   }
+
+   pub fn vsync(&self) -> bool {
+      self.vsync
+   }
+
+   pub fn palette(&self) -> Option<&Palette> {
+      self.palette.as_ref()
+   }
+
+   pub fn headless(&self) -> bool {
+      self.headless
+   }
+
+   pub fn log_level(&self) -> LogLevel {
+      self.log_level
+   }
+
+   pub fn input_recording(&self) -> Option<&Path> {
+      self.input_recording.as_ref().map(|path| path.as_path())
+   }
+
+   pub fn input_playback(&self) -> Option<&Path> {
+      self.input_playback.as_ref().map(|path| path.as_path())
+   }
+
+   /// Prints `text` if the log level includes `level`.
+   pub fn log(&self, level: LogLevel, text: &str) {
+      if level != LogLevel::Off && level <= self.log_level {
+         println!("{}", text);
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn builder() {
+      let config = Config::new("test", 320, 200)
+         .with_scale(0)
+         .with_step_rate(20)
+         .with_frame_rate(None)
+         .with_scaling(Scaling::Fit)
+         .with_palette(palette::dawn_bringer::create_palette())
+         .with_log_level(LogLevel::Error);
+
+      assert_eq!(config.title(), "test");
+      assert_eq!((config.width(), config.height(), config.scale()), (320, 200, 1));
+      assert_eq!(config.timestep(), Timestep { step_rate: 20, max_steps: 5, frame_rate: None });
+      assert_eq!(config.scaling(), Scaling::Fit);
+      assert!(!config.resizable() && !config.vsync());
+      assert_eq!(config.palette().map(|p| p.colors().len()), Some(33));
+      assert!(config.log_level() > LogLevel::Off && config.log_level() < LogLevel::Info);
+   }
+}
@@ -10,7 +10,7 @@ pub fn font_4x7_bitmap() -> Bitmap {
 }
 
 pub fn font_4x7() -> Font {
-	Font::new(font_4x7_bitmap(), FONT_4X7_CHAR_WIDTH, FONT_4X7_CHAR_HEIGHT, FONT_4X7_LINE_HEIGHT)
+	with_aliases(Font::new(font_4x7_bitmap(), FONT_4X7_CHAR_WIDTH, FONT_4X7_CHAR_HEIGHT, FONT_4X7_LINE_HEIGHT))
 }
 
 pub fn font_4x10_bitmap() -> Bitmap {
@@ -18,5 +18,19 @@ pub fn font_4x10_bitmap() -> Bitmap {
 }
 
 pub fn font_4x10() -> Font {
-	Font::new(font_4x10_bitmap(), FONT_4X10_CHAR_WIDTH, FONT_4X10_CHAR_HEIGHT, FONT_4X10_LINE_HEIGHT)
-}
\ No newline at end of file
+	with_aliases(Font::new(font_4x10_bitmap(), FONT_4X10_CHAR_WIDTH, FONT_4X10_CHAR_HEIGHT, FONT_4X10_LINE_HEIGHT))
+}
+
+/// The default fonts only hold ASCII, draw typographic punctuation with the closest ASCII glyph.
+fn with_aliases(mut font: Font) -> Font {
+	let aliases = [
+		('\u{A0}', ' '), ('\u{2018}', '\''), ('\u{2019}', '\''), ('\u{201A}', ','),
+		('\u{201C}', '"'), ('\u{201D}', '"'), ('\u{201E}', '"'), ('\u{2013}', '-'), ('\u{2014}', '-'),
+	];
+
+	for &(ch, like) in aliases.iter() {
+		font.alias(ch, like);
+	}
+
+	font
+}
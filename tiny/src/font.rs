@@ -1,48 +1,265 @@
-
 use super::*;
 
+use std::collections::HashMap;
+
+/// Where a glyph is in the font bitmap and how it is placed.
+///
+/// The glyph is drawn `offset_x`, `offset_y` away from the pen position, which then moves
+/// `advance` pixels to the right.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Glyph {
+   pub rect: Rect,
+   pub offset_x: i32,
+   pub offset_y: i32,
+   pub advance: i32,
+}
+
+impl Glyph {
+   pub fn new(rect: Rect, advance: i32) -> Glyph {
+      Glyph {
+         rect: rect,
+         offset_x: 0,
+         offset_y: 0,
+         advance: advance,
+      }
+   }
+}
+
+/// A bitmap font mapping any `char` to a glyph.
+///
+/// `char_width` is the advance of a space and of tabs, for monospaced fonts it is the
+/// width of every character. Characters the font has no glyph for are drawn as the
+/// fallback character, `?` unless changed with `set_fallback`.
 #[derive(Clone)]
 pub struct Font {
     pub bitmap: Bitmap,
     pub char_width: i32,
     pub char_height: i32,
     pub line_height: i32,
+
+    glyphs: HashMap<char, Glyph>,
+    fallback: Option<char>,
 }
 
 impl Font {
+   /// A monospaced font from a grid of `char_width` x `char_height` cells holding the
+   /// characters in code order, starting with `'\0'` in the top left.
    pub fn new(bitmap: Bitmap, char_width: u32, char_height: u32, line_height: u32) -> Font {
+      let chars = (0..256u32).filter_map(::std::char::from_u32).collect::<String>();
+      Font::from_grid(bitmap, char_width, char_height, line_height, &chars)
+   }
+
+   /// A monospaced font from a grid of cells holding `chars` in order, row by row. Characters
+   /// past the end of the bitmap are left out.
+   pub fn from_grid(bitmap: Bitmap, char_width: u32, char_height: u32, line_height: u32, chars: &str) -> Font {
+      let mut font = Font::empty(bitmap, char_height, line_height);
+      font.char_width = char_width as i32;
+
+      let columns = font.bitmap.width / cmp::max(char_width, 1);
+      let cells = columns * (font.bitmap.height / cmp::max(char_height, 1));
+
+      for (idx, ch) in chars.chars().enumerate().take(cells as usize) {
+         let x = idx as u32 % columns * char_width;
+         let y = idx as u32 / columns * char_height;
+         font.add_glyph(ch, Glyph::new(Rect::new_size(x as i32, y as i32, char_width as i32, char_height as i32), char_width as i32));
+      }
+
+      font
+   }
+
+   /// A font without any glyphs yet, see `add_glyph`.
+   pub fn empty(bitmap: Bitmap, char_height: u32, line_height: u32) -> Font {
       Font {
          bitmap: bitmap,
-         char_width: char_width as i32,
+         char_width: 0,
          char_height: char_height as i32,
          line_height: line_height as i32,
+         glyphs: HashMap::new(),
+         fallback: Some('?'),
+      }
+   }
+
+   /// Adds or replaces the glyph for `ch`. Adding a space also sets `char_width`.
+   pub fn add_glyph(&mut self, ch: char, glyph: Glyph) {
+      if ch == ' ' {
+         self.char_width = glyph.advance;
+      }
+
+      self.glyphs.insert(ch, glyph);
+   }
+
+   /// Draws `ch` with the glyph of `like`, for example typographic quotes as plain ones.
+   /// Returns false when there is no glyph for `like`.
+   pub fn alias(&mut self, ch: char, like: char) -> bool {
+      match self.glyphs.get(&like).cloned() {
+         Some(glyph) => {
+            self.add_glyph(ch, glyph);
+            true
+         },
+         None => false,
+      }
+   }
+
+   pub fn glyph(&self, ch: char) -> Option<&Glyph> {
+      self.glyphs.get(&ch)
+   }
+
+   pub fn has_glyph(&self, ch: char) -> bool {
+      self.glyphs.contains_key(&ch)
+   }
+
+   /// Character drawn in place of characters without a glyph, `None` skips them.
+   pub fn set_fallback(&mut self, fallback: Option<char>) {
+      self.fallback = fallback;
+   }
+
+   /// The glyph `ch` is drawn with, if any. Spaces and tabs advance by `char_width` even
+   /// when the font has no glyph for them.
+   pub fn glyph_or_fallback(&self, ch: char) -> Option<Glyph> {
+      let glyph = match ch {
+         '\t' => None,
+         _ => self.glyphs.get(&ch).or_else(|| self.fallback.and_then(|fallback| self.glyphs.get(&fallback))),
+      };
+
+      match ch {
+         ' ' | '\t' if glyph.is_none() => Some(Glyph::new(Rect::new_size(0, 0, 0, 0), self.char_width)),
+         _ => glyph.cloned(),
+      }
+   }
+
+   /// How far the pen moves for `ch`.
+   pub fn advance(&self, ch: char) -> i32 {
+      self.glyph_or_fallback(ch).map(|glyph| glyph.advance).unwrap_or(0)
+   }
+
+   /// Sets the advance of every glyph to the width of its set pixels plus `spacing`, turning
+   /// a monospaced grid into a proportional font. Empty glyphs like space keep their advance.
+   pub fn make_proportional(&mut self, spacing: i32) {
+      let pixels = self.bitmap.pixels.borrow();
+      let width = self.bitmap.width as i32;
+
+      for glyph in self.glyphs.values_mut() {
+         let rect = glyph.rect.intersect(Rect::new_size(0, 0, width, self.bitmap.height as i32));
+
+         let used = (rect.left..rect.right).rev().find(|&x| {
+            (rect.top..rect.bottom).any(|y| pixels[(x + y * width) as usize] != TRANSPARENT)
+         });
+
+         if let Some(x) = used {
+            glyph.advance = x + 1 - glyph.rect.left + glyph.offset_x + spacing;
+         }
       }
    }
 
+   /// Size of `text` as drawn by `Painter::text`, colour tags take up no space.
    pub fn measure(&self, text: &str) -> Rect {
       let mut x_curr = 0;
       let mut x_max = 0;
       let mut y_max = self.char_height;
 
-      for ch in text.chars() {
-         let idx = ch as u32;
-         if idx < 256 {
-            match ch {
-               ' ' => x_curr += self.char_width,
-               '\t' => x_curr += self.char_width,
-               '\n' => {
-                  x_curr = 0;
-                  y_max += self.line_height;
-               },
-               _ => x_curr += self.char_width,
-            }
-
-            if x_curr > x_max {
-               x_max = x_curr;
-            }
+      for (_, markup) in parse_markup(text) {
+         match markup {
+            Markup::Char('\n') => {
+               x_curr = 0;
+               y_max += self.line_height;
+            },
+            Markup::Char(ch) => x_curr += self.advance(ch),
+            Markup::Color(_) => {},
+         }
+
+         if x_curr > x_max {
+            x_max = x_curr;
          }
       }
 
       Rect::new_size(0, 0, x_max, y_max)
    }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   /// Three cells of 4x3, each filled as wide as the cell number plus one.
+   fn test_font() -> Font {
+      let mut bitmap = Bitmap::new(12, 3);
+      {
+         let painter = BitmapPainter::new(&mut bitmap);
+         painter.rect_fill(Rect::new_size(0, 0, 1, 3), WHITE);
+         painter.rect_fill(Rect::new_size(4, 0, 2, 3), WHITE);
+         painter.rect_fill(Rect::new_size(8, 0, 3, 3), WHITE);
+      }
+
+      Font::from_grid(bitmap, 4, 3, 5, "ąЖ€")
+   }
+
+   #[test]
+   fn unicode_glyphs() {
+      let mut font = test_font();
+
+      assert!(font.has_glyph('ą') && font.has_glyph('€') && !font.has_glyph('a'));
+      assert!(font.glyph('Ж') == Some(&Glyph::new(Rect::new_size(4, 0, 4, 3), 4)));
+      assert_eq!(font.measure("ąЖ\n€€").width(), 8);
+      assert_eq!(font.measure("ąЖ\n€€").height(), 8);
+
+      // No space or fallback glyph, spaces still advance and unknown characters are skipped
+      assert_eq!(font.advance(' '), 4);
+      assert_eq!(font.advance('x'), 0);
+
+      assert!(font.alias('?', '€') && !font.alias('”', '"'));
+      assert_eq!(font.advance('x'), 4);
+      font.set_fallback(None);
+      assert_eq!(font.advance('x'), 0);
+   }
+
+   #[test]
+   fn proportional() {
+      let mut font = test_font();
+      font.make_proportional(1);
+
+      assert_eq!((font.advance('ą'), font.advance('Ж'), font.advance('€')), (2, 3, 4));
+      assert_eq!(font.measure("ąЖ€ ").width(), 2 + 3 + 4 + 4);
+
+      let mut canvas = Bitmap::new(12, 3);
+      BitmapPainter::new(&mut canvas).text(0, 0, "ąЖ€", BLACK, &font);
+
+      let row = (0..12).map(|x| canvas.pixel(x, 1)).collect::<Vec<_>>();
+      assert_eq!(row, [BLACK, 0, BLACK, BLACK, 0, BLACK, BLACK, BLACK, 0, 0, 0, 0]);
+   }
+
+   #[test]
+   fn proportional_rows() {
+      // Glyphs only look at their own cell, not at the one below
+      let mut bitmap = Bitmap::new(8, 6);
+      {
+         let painter = BitmapPainter::new(&mut bitmap);
+         painter.rect_fill(Rect::new_size(0, 0, 1, 3), WHITE);
+         painter.rect_fill(Rect::new_size(4, 0, 2, 3), WHITE);
+         painter.rect_fill(Rect::new_size(0, 3, 3, 3), WHITE);
+      }
+
+      let mut font = Font::from_grid(bitmap, 4, 3, 5, "abc");
+      font.make_proportional(0);
+
+      assert_eq!((font.advance('a'), font.advance('b'), font.advance('c')), (1, 2, 3));
+   }
+
+   #[test]
+   fn default_font() {
+      let font = default_font::font_4x10();
+
+      assert_eq!(font.measure("TINY RTS").width(), 8 * 4);
+      assert_eq!(font.advance('\u{201C}'), font.advance('"'));
+      assert!(font.glyph_or_fallback('Ω').map(|g| g.rect) == font.glyph('?').map(|g| g.rect));
+   }
+
+   #[test]
+   fn markup() {
+      let font = default_font::font_4x10();
+
+      assert_eq!(font.measure("{mandy}Error:{} {{x}").width(), 10 * 4);
+      assert_eq!(font.measure("{19}ab\n{not a {tag}").height(), font.char_height + font.line_height);
+      assert_eq!(font.measure("{x!}").width(), 4 * 4);
+   }
+}
@@ -0,0 +1,434 @@
+use super::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+/// Loaders for pixel fonts made with other tools.
+///
+/// Font bitmaps only hold which pixels are set, colour is picked when drawing text.
+impl Font {
+   /// Loads an AngelCode BMFont in the text format, along with the page images it lists
+   /// next to it. Pixels with an alpha of at least half are set. Kerning pairs are ignored.
+   pub fn load_bmfont(path: &Path) -> Result<Font, String> {
+      let text = read_text(path)?;
+      let dir = path.parent().unwrap_or(Path::new(""));
+
+      parse_bmfont(&text, |file| load_page(&dir.join(file)))
+         .map_err(|err| format!("Could not load font {}: {}", path.display(), err))
+   }
+
+   /// Loads an X11 BDF font. Encodings are taken to be Unicode code points, which holds
+   /// for ISO 10646 and ISO 8859-1 fonts.
+   pub fn load_bdf(path: &Path) -> Result<Font, String> {
+      let text = read_text(path)?;
+
+      Font::parse_bdf(&text)
+         .map_err(|err| format!("Could not load font {}: {}", path.display(), err))
+   }
+
+   /// Parses the contents of a BDF file, see `load_bdf`.
+   pub fn parse_bdf(text: &str) -> Result<Font, String> {
+      let mut ascent = None;
+      let mut descent = None;
+      let mut bounds = None;
+      let mut default_advance = None;
+      let mut chars = Vec::new();
+
+      let mut lines = text.lines().enumerate();
+      while let Some((idx, line)) = lines.next() {
+         let mut words = line.split_whitespace();
+         let error = |msg: &str| format!("line {}: {}", idx + 1, msg);
+
+         match words.next() {
+            Some("FONT_ASCENT") => ascent = Some(parse_numbers(words, 1).map_err(|e| error(&e))?[0]),
+            Some("FONT_DESCENT") => descent = Some(parse_numbers(words, 1).map_err(|e| error(&e))?[0]),
+            Some("FONTBOUNDINGBOX") => bounds = Some(parse_numbers(words, 4).map_err(|e| error(&e))?),
+            Some("DWIDTH") => default_advance = Some(parse_numbers(words, 2).map_err(|e| error(&e))?[0]),
+            Some("STARTCHAR") => chars.push(parse_bdf_char(&mut lines)?),
+            _ => (),
+         }
+      }
+
+      let bounds = match bounds {
+         Some(bounds) => bounds,
+         None => return Err(String::from("missing FONTBOUNDINGBOX")),
+      };
+
+      let ascent = ascent.unwrap_or(bounds[1] + bounds[3]);
+      let descent = descent.unwrap_or(-bounds[3]);
+
+      // Glyphs are packed into cells as large as the largest glyph, 16 to a row
+      let chars = chars.into_iter().filter(|c| c.ch.is_some()).collect::<Vec<_>>();
+      let cell_width = chars.iter().map(|c| c.width).max().unwrap_or(0);
+      let cell_height = chars.iter().map(|c| c.rows.len() as i32).max().unwrap_or(0);
+      let columns = 16;
+      let rows = (chars.len() as i32 + columns - 1) / columns;
+
+      let bitmap = Bitmap::new((columns * cell_width) as u32, (rows * cell_height) as u32);
+      let mut glyphs = Vec::new();
+      {
+         let mut pixels = bitmap.pixels.borrow_mut();
+
+         for (idx, c) in chars.iter().enumerate() {
+            let left = idx as i32 % columns * cell_width;
+            let top = idx as i32 / columns * cell_height;
+
+            for (y, row) in c.rows.iter().enumerate() {
+               for x in 0..c.width {
+                  if row.get(x as usize / 8).map_or(false, |byte| byte & (0x80 >> (x % 8)) != 0) {
+                     pixels[(left + x + (top + y as i32) * bitmap.width as i32) as usize] = WHITE;
+                  }
+               }
+            }
+
+            let height = c.rows.len() as i32;
+            glyphs.push((c.ch.unwrap(), Glyph {
+               rect: Rect::new_size(left, top, c.width, height),
+               offset_x: c.x_offset,
+               offset_y: ascent - height - c.y_offset,
+               advance: c.advance.or(default_advance).unwrap_or(bounds[0]),
+            }));
+         }
+      }
+
+      let height = cmp::max(ascent + descent, 0) as u32;
+      let mut font = Font::empty(bitmap, height, height);
+      font.char_width = bounds[0];
+      for (ch, glyph) in glyphs {
+         font.add_glyph(ch, glyph);
+      }
+
+      Ok(font)
+   }
+}
+
+
+struct BdfChar {
+   ch: Option<char>,
+   advance: Option<i32>,
+   width: i32,
+   x_offset: i32,
+   y_offset: i32,
+   rows: Vec<Vec<u8>>,
+}
+
+fn parse_bdf_char<'a, I: Iterator<Item=(usize, &'a str)>>(lines: &mut I) -> Result<BdfChar, String> {
+   let mut c = BdfChar { ch: None, advance: None, width: 0, x_offset: 0, y_offset: 0, rows: Vec::new() };
+   let mut height = 0;
+   let mut in_bitmap = false;
+
+   for (idx, line) in lines {
+      let mut words = line.split_whitespace();
+      let error = |msg: &str| format!("line {}: {}", idx + 1, msg);
+
+      match words.next() {
+         Some("ENDCHAR") => {
+            if c.rows.len() != height {
+               return Err(error(&format!("expected {} bitmap rows, found {}", height, c.rows.len())));
+            }
+
+            return Ok(c);
+         },
+         Some(word) if in_bitmap => {
+            // Whole bytes of hex digits only, anything else would be cut mid character or nibble
+            if word.len() % 2 != 0 || !word.bytes().all(|b| b.is_ascii_hexdigit()) {
+               return Err(error(&format!("invalid bitmap row '{}'", word)));
+            }
+
+            c.rows.push((0..word.len() / 2).map(|i| u8::from_str_radix(&word[i * 2..i * 2 + 2], 16).unwrap()).collect());
+         },
+         Some("ENCODING") => {
+            let code = parse_numbers(words, 1).map_err(|e| error(&e))?[0];
+            c.ch = if code >= 0 { ::std::char::from_u32(code as u32) } else { None };
+         },
+         Some("DWIDTH") => c.advance = Some(parse_numbers(words, 2).map_err(|e| error(&e))?[0]),
+         Some("BBX") => {
+            let bbx = parse_numbers(words, 4).map_err(|e| error(&e))?;
+            c.width = cmp::max(bbx[0], 0);
+            height = cmp::max(bbx[1], 0) as usize;
+            c.x_offset = bbx[2];
+            c.y_offset = bbx[3];
+         },
+         Some("BITMAP") => in_bitmap = true,
+         _ => (),
+      }
+   }
+
+   Err(String::from("missing ENDCHAR"))
+}
+
+fn parse_numbers<'a, I: Iterator<Item=&'a str>>(words: I, count: usize) -> Result<Vec<i32>, String> {
+   let numbers = words.take(count).map(|word| word.parse::<i32>()).collect::<Result<Vec<_>, _>>();
+
+   match numbers {
+      Ok(ref numbers) if numbers.len() == count => Ok(numbers.clone()),
+      _ => Err(format!("expected {} numbers", count)),
+   }
+}
+
+
+/// Parses a BMFont text file, `load_page` loads the page images by file name.
+fn parse_bmfont<F: FnMut(&str) -> Result<Bitmap, String>>(text: &str, mut load_page: F) -> Result<Font, String> {
+   let mut line_height = None;
+   let mut pages = Vec::new();
+   let mut chars = Vec::new();
+
+   for (idx, line) in text.lines().enumerate() {
+      let (tag, values) = parse_bmfont_line(line);
+      let value = |key: &str| match values.get(key).map(|value| value.parse::<i32>()) {
+         Some(Ok(value)) => Ok(value),
+         _ => Err(format!("line {}: missing or invalid '{}'", idx + 1, key)),
+      };
+
+      match tag {
+         "common" => line_height = Some(value("lineHeight")?),
+         "page" => {
+            let id = value("id")?;
+            if !(0..=255).contains(&id) {
+               return Err(format!("line {}: invalid page id {}", idx + 1, id));
+            }
+
+            let id = id as usize;
+            let file = match values.get("file") {
+               Some(file) => file,
+               None => return Err(format!("line {}: missing 'file'", idx + 1)),
+            };
+
+            if pages.len() <= id {
+               pages.resize(id + 1, None);
+            }
+            pages[id] = Some(load_page(file)?);
+         },
+         "char" => {
+            let rect = Rect::new_size(value("x")?, value("y")?, value("width")?, value("height")?);
+            let glyph = Glyph {
+               rect: rect,
+               offset_x: value("xoffset")?,
+               offset_y: value("yoffset")?,
+               advance: value("xadvance")?,
+            };
+
+            chars.push((value("id")?, value("page").unwrap_or(0) as usize, glyph));
+         },
+         _ => (),
+      }
+   }
+
+   let line_height = match line_height {
+      Some(line_height) => cmp::max(line_height, 0) as u32,
+      None => return Err(String::from("missing 'common' line")),
+   };
+
+   // Pages are stacked on top of each other into a single bitmap
+   let mut tops = Vec::new();
+   let mut width = 0;
+   let mut height = 0;
+   for page in pages.iter() {
+      tops.push(height as i32);
+      if let Some(ref page) = *page {
+         width = cmp::max(width, page.width);
+         height += page.height;
+      }
+   }
+
+   let mut bitmap = Bitmap::new(width, height);
+   {
+      let painter = BitmapPainter::new(&mut bitmap);
+      for (page, &top) in pages.iter().zip(tops.iter()) {
+         if let Some(ref page) = *page {
+            painter.blit(0, top, page, Rect::new_size(0, 0, page.width as i32, page.height as i32), 0, 0);
+         }
+      }
+   }
+
+   let mut font = Font::empty(bitmap, line_height, line_height);
+   for (id, page, mut glyph) in chars {
+      let ch = match ::std::char::from_u32(id as u32) {
+         Some(ch) if id >= 0 => ch,
+         _ => continue,
+      };
+
+      match tops.get(page) {
+         Some(&top) if pages[page].is_some() => glyph.rect = glyph.rect.tr(0, top),
+         _ => return Err(format!("character {} is on missing page {}", id, page)),
+      }
+
+      font.add_glyph(ch, glyph);
+   }
+
+   Ok(font)
+}
+
+/// Splits `tag key=value key="quoted value"` into the tag and its values.
+fn parse_bmfont_line(line: &str) -> (&str, HashMap<&str, &str>) {
+   let line = line.trim();
+   let (tag, mut rest) = match line.find(char::is_whitespace) {
+      Some(idx) => (&line[..idx], line[idx..].trim_start()),
+      None => (line, ""),
+   };
+
+   let mut values = HashMap::new();
+   while let Some(eq) = rest.find('=') {
+      let key = rest[..eq].trim();
+      let after = &rest[eq + 1..];
+
+      let (value, next) = if let Some(quoted) = after.strip_prefix('"') {
+         match quoted.find('"') {
+            Some(end) => (&quoted[..end], &quoted[end + 1..]),
+            None => (quoted, ""),
+         }
+      } else {
+         match after.find(char::is_whitespace) {
+            Some(end) => (&after[..end], &after[end..]),
+            None => (after, ""),
+         }
+      };
+
+      values.insert(key, value);
+      rest = next.trim_start();
+   }
+
+   (tag, values)
+}
+
+fn load_page(path: &Path) -> Result<Bitmap, String> {
+   let img = match image::open(path) {
+      Ok(img) => img.to_rgba(),
+      Err(err) => return Err(format!("Could not load page {}: {}", path.display(), err)),
+   };
+
+   let bitmap = Bitmap::new(img.width(), img.height());
+   for (pixel, rgba) in bitmap.pixels.borrow_mut().iter_mut().zip(img.pixels()) {
+      if rgba[3] >= 128 {
+         *pixel = WHITE;
+      }
+   }
+
+   Ok(bitmap)
+}
+
+fn read_text(path: &Path) -> Result<String, String> {
+   let mut text = String::new();
+
+   match File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+      Ok(_) => Ok(text),
+      Err(err) => Err(format!("Could not load font {}: {}", path.display(), err)),
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use palette::dawn_bringer as pal;
+
+   use std::env;
+   use std::fs;
+
+   const BDF: &'static str = "\
+STARTFONT 2.1
+FONT -test-fixed-medium-r-normal--5-50-75-75-c-40-iso10646-1
+SIZE 5 75 75
+FONTBOUNDINGBOX 4 6 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 5
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 3
+STARTCHAR space
+ENCODING 32
+DWIDTH 4 0
+BBX 0 0 0 0
+BITMAP
+ENDCHAR
+STARTCHAR uni0416
+ENCODING 1046
+DWIDTH 4 0
+BBX 3 5 0 0
+BITMAP
+A0
+40
+E0
+40
+A0
+ENDCHAR
+STARTCHAR comma
+ENCODING 44
+DWIDTH 2 0
+BBX 1 2 0 -1
+BITMAP
+80
+80
+ENDCHAR
+ENDFONT
+";
+
+   #[test]
+   fn bdf() {
+      let font = Font::parse_bdf(BDF).unwrap();
+
+      assert_eq!((font.char_width, font.char_height, font.line_height), (4, 6, 6));
+      assert_eq!(font.measure("Ж Ж,").width(), 4 + 4 + 4 + 2);
+
+      // The comma hangs one pixel below the baseline
+      let comma = font.glyph(',').unwrap();
+      assert_eq!((comma.offset_x, comma.offset_y, comma.rect.height()), (0, 4, 2));
+
+      let mut canvas = Bitmap::new(8, 6);
+      BitmapPainter::new(&mut canvas).text(0, 0, "Ж,", BLACK, &font);
+      let rows = (0..6).map(|y| (0..6).map(|x| if canvas.pixel(x, y) == BLACK { '#' } else { '.' }).collect::<String>()).collect::<Vec<_>>();
+      assert_eq!(rows, ["#.#...", ".#....", "###...", ".#....", "#.#.#.", "....#."]);
+
+      assert!(Font::parse_bdf("STARTFONT 2.1\nENDFONT\n").is_err());
+      assert!(Font::parse_bdf(&BDF.replace("E0\n", "")).is_err());
+      assert!(Font::parse_bdf(&BDF.replace("E0\n", "XY\n")).is_err());
+      assert!(Font::parse_bdf(&BDF.replace("E0\n", "€0\n")).is_err());
+      assert!(Font::parse_bdf(&BDF.replace("E0\n", "E\n")).is_err());
+   }
+
+   #[test]
+   fn bmfont() {
+      let dir = env::temp_dir().join(format!("tiny-bmfont-{}", std::process::id()));
+      fs::create_dir_all(&dir).unwrap();
+
+      let mut page = Bitmap::new(8, 4);
+      BitmapPainter::new(&mut page).rect_fill(Rect::new_size(2, 1, 3, 2), pal::WHITE);
+      page.save(&dir.join("test_0.png"), &pal::create_palette()).unwrap();
+
+      let fnt = "\
+info face=\"Test Font\" size=8 bold=0 italic=0 charset=\"\" unicode=1
+common lineHeight=7 base=6 scaleW=8 scaleH=4 pages=1 packed=0
+page id=0 file=\"test_0.png\"
+chars count=2
+char id=8364 x=2 y=1 width=3 height=2 xoffset=1 yoffset=3 xadvance=5 page=0 chnl=15
+char id=32   x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=3 page=0 chnl=15
+kernings count=0
+";
+      fs::write(dir.join("test.fnt"), fnt).unwrap();
+
+      let font = Font::load_bmfont(&dir.join("test.fnt")).unwrap();
+      assert_eq!((font.char_width, font.char_height, font.line_height), (3, 7, 7));
+      assert_eq!(font.measure("€ €").width(), 13);
+
+      let mut canvas = Bitmap::new(8, 8);
+      BitmapPainter::new(&mut canvas).text(0, 0, "€", BLACK, &font);
+      assert_eq!(canvas.pixel(1, 3), BLACK);
+      assert_eq!(canvas.pixel(3, 4), BLACK);
+      assert_eq!(canvas.pixel(0, 3), TRANSPARENT);
+      assert_eq!(canvas.pixel(4, 3), TRANSPARENT);
+
+      fs::write(dir.join("missing.fnt"), fnt.replace("test_0.png", "missing.png")).unwrap();
+      assert!(Font::load_bmfont(&dir.join("missing.fnt")).is_err());
+      assert!(Font::load_bmfont(&dir.join("none.fnt")).is_err());
+
+      fs::remove_dir_all(&dir).unwrap();
+   }
+
+   #[test]
+   fn bmfont_line() {
+      let (tag, values) = parse_bmfont_line("page id=0  file=\"my font 0.png\" x=-1");
+      assert_eq!(tag, "page");
+      assert_eq!((values["id"], values["file"], values["x"]), ("0", "my font 0.png", "-1"));
+   }
+}
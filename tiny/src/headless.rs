@@ -0,0 +1,152 @@
+use super::*;
+
+use platform::headless::Event;
+
+/// Drives an `Application` without opening a window, one frame at a time.
+///
+/// Input is scripted through the `key_*`, `mouse_*` and `text` methods and is
+/// picked up by the application on the next call to `step`.
+pub struct Headless<T: Application> {
+   context: Context,
+   canvas: Bitmap,
+   app: T,
+   running: bool,
+}
+
+impl<T: Application> Headless<T> {
+   pub fn new(width: u32, height: u32) -> Result<Headless<T>, String> {
+      Headless::with_config(Config::new("headless", width, height))
+   }
+
+   /// Starts the application with `config` as `run` would, but always headless.
+   pub fn with_config(config: Config) -> Result<Headless<T>, String> {
+      let config = T::configure(config).with_headless(true);
+      let canvas = Bitmap::new(config.width(), config.height());
+
+      let mut context = Context::new(config)?;
+      let app = T::new(&mut context)?;
+
+      Ok(Headless {
+         context: context,
+         canvas: canvas,
+         app: app,
+         running: true,
+      })
+   }
+
+   /// Runs `frames` frames of exactly one step each, stopping early if the application quits.
+   /// Returns whether the application is still running.
+   pub fn step(&mut self, frames: u32) -> Result<bool, String> {
+      let step_length = self.context.timestep.step_length();
+
+      for _ in 0..frames {
+         self.advance(step_length)?;
+      }
+
+      Ok(self.running)
+   }
+
+   /// Runs a single frame as if `seconds` of real time had passed since the previous one.
+   pub fn advance(&mut self, seconds: f64) -> Result<bool, String> {
+      if self.running {
+         self.running = frame(&mut self.context, &mut self.app, &mut self.canvas, seconds)?;
+      }
+
+      Ok(self.running)
+   }
+
+   /// Runs every frame of an input recording, with the frame times it was recorded with.
+   /// Returns whether the application is still running.
+   pub fn replay<P: AsRef<Path>>(&mut self, path: P) -> Result<bool, String> {
+      self.context.start_input_playback(path)?;
+
+      while self.running && self.context.playing_input() {
+         self.advance(0.0)?;
+      }
+
+      self.context.stop_input_playback();
+      Ok(self.running)
+   }
+
+   pub fn set_timestep(&mut self, timestep: Timestep) {
+      self.context.set_timestep(timestep);
+   }
+
+   pub fn running(&self) -> bool {
+      self.running
+   }
+
+   /// Number of frames that have been painted so far.
+   pub fn frames(&self) -> u64 {
+      match self.context.window {
+         platform::Window::Headless(ref window) => window.frames,
+         _ => 0,
+      }
+   }
+
+   pub fn canvas(&self) -> &Bitmap {
+      &self.canvas
+   }
+
+   pub fn palette(&self) -> Palette {
+      self.context.palette()
+   }
+
+   pub fn context(&self) -> &Context {
+      &self.context
+   }
+
+   pub fn context_mut(&mut self) -> &mut Context {
+      &mut self.context
+   }
+
+   pub fn app(&self) -> &T {
+      &self.app
+   }
+
+   pub fn app_mut(&mut self) -> &mut T {
+      &mut self.app
+   }
+
+   pub fn key_down(&mut self, key: Key) {
+      self.push(Event::KeyDown(key));
+   }
+
+   pub fn key_up(&mut self, key: Key) {
+      self.push(Event::KeyUp(key));
+   }
+
+   pub fn mouse_move(&mut self, x: u32, y: u32) {
+      self.push(Event::MouseMove(x, y));
+   }
+
+   pub fn mouse_down(&mut self, button: Mouse) {
+      self.push(Event::MouseDown(button));
+   }
+
+   pub fn mouse_up(&mut self, button: Mouse) {
+      self.push(Event::MouseUp(button));
+   }
+
+   /// Scrolls the wheel by a number of lines, positive `y` is away from the user.
+   pub fn mouse_wheel(&mut self, x: f32, y: f32) {
+      self.push(Event::MouseWheel(x, y));
+   }
+
+   pub fn text(&mut self, text: &str) {
+      for ch in text.chars() {
+         self.push(Event::Text(ch));
+      }
+   }
+
+   /// Simulates the user closing the window.
+   pub fn close(&mut self) {
+      self.push(Event::Close);
+   }
+
+   fn push(&mut self, event: Event) {
+      if let Some(window) = self.context.window.headless_mut() {
+         window.events.push_back(event);
+      }
+   }
+}
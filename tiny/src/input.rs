@@ -159,4 +159,203 @@ pub enum Mouse {
    Left,
    Right,
    Middle,
-}
\ No newline at end of file
+   X1,
+   X2,
+}
+
+/// Names used for keys in text files, see `Key::name`.
+const KEY_NAMES: [(Key, &'static str); 151] = [
+   (Key::Key1, "1"),
+   (Key::Key2, "2"),
+   (Key::Key3, "3"),
+   (Key::Key4, "4"),
+   (Key::Key5, "5"),
+   (Key::Key6, "6"),
+   (Key::Key7, "7"),
+   (Key::Key8, "8"),
+   (Key::Key9, "9"),
+   (Key::Key0, "0"),
+   (Key::A, "a"),
+   (Key::B, "b"),
+   (Key::C, "c"),
+   (Key::D, "d"),
+   (Key::E, "e"),
+   (Key::F, "f"),
+   (Key::G, "g"),
+   (Key::H, "h"),
+   (Key::I, "i"),
+   (Key::J, "j"),
+   (Key::K, "k"),
+   (Key::L, "l"),
+   (Key::M, "m"),
+   (Key::N, "n"),
+   (Key::O, "o"),
+   (Key::P, "p"),
+   (Key::Q, "q"),
+   (Key::R, "r"),
+   (Key::S, "s"),
+   (Key::T, "t"),
+   (Key::U, "u"),
+   (Key::V, "v"),
+   (Key::W, "w"),
+   (Key::X, "x"),
+   (Key::Y, "y"),
+   (Key::Z, "z"),
+   (Key::Escape, "escape"),
+   (Key::F1, "f1"),
+   (Key::F2, "f2"),
+   (Key::F3, "f3"),
+   (Key::F4, "f4"),
+   (Key::F5, "f5"),
+   (Key::F6, "f6"),
+   (Key::F7, "f7"),
+   (Key::F8, "f8"),
+   (Key::F9, "f9"),
+   (Key::F10, "f10"),
+   (Key::F11, "f11"),
+   (Key::F12, "f12"),
+   (Key::F13, "f13"),
+   (Key::F14, "f14"),
+   (Key::F15, "f15"),
+   (Key::Snapshot, "snapshot"),
+   (Key::Scroll, "scroll"),
+   (Key::Pause, "pause"),
+   (Key::Insert, "insert"),
+   (Key::Home, "home"),
+   (Key::Delete, "delete"),
+   (Key::End, "end"),
+   (Key::PageDown, "page-down"),
+   (Key::PageUp, "page-up"),
+   (Key::Left, "left"),
+   (Key::Up, "up"),
+   (Key::Right, "right"),
+   (Key::Down, "down"),
+   (Key::Back, "back"),
+   (Key::Return, "return"),
+   (Key::Space, "space"),
+   (Key::Compose, "compose"),
+   (Key::Caret, "caret"),
+   (Key::Numlock, "numlock"),
+   (Key::Numpad0, "numpad0"),
+   (Key::Numpad1, "numpad1"),
+   (Key::Numpad2, "numpad2"),
+   (Key::Numpad3, "numpad3"),
+   (Key::Numpad4, "numpad4"),
+   (Key::Numpad5, "numpad5"),
+   (Key::Numpad6, "numpad6"),
+   (Key::Numpad7, "numpad7"),
+   (Key::Numpad8, "numpad8"),
+   (Key::Numpad9, "numpad9"),
+   (Key::AbntC1, "abnt-c1"),
+   (Key::AbntC2, "abnt-c2"),
+   (Key::Add, "add"),
+   (Key::Apostrophe, "apostrophe"),
+   (Key::Apps, "apps"),
+   (Key::At, "at"),
+   (Key::Ax, "ax"),
+   (Key::Backslash, "backslash"),
+   (Key::Calculator, "calculator"),
+   (Key::Capital, "capital"),
+   (Key::Colon, "colon"),
+   (Key::Comma, "comma"),
+   (Key::Convert, "convert"),
+   (Key::Decimal, "decimal"),
+   (Key::Divide, "divide"),
+   (Key::Equals, "equals"),
+   (Key::Grave, "grave"),
+   (Key::Kana, "kana"),
+   (Key::Kanji, "kanji"),
+   (Key::LAlt, "lalt"),
+   (Key::LBracket, "lbracket"),
+   (Key::LControl, "lcontrol"),
+   (Key::LMenu, "lmenu"),
+   (Key::LShift, "lshift"),
+   (Key::LWin, "lwin"),
+   (Key::Mail, "mail"),
+   (Key::MediaSelect, "media-select"),
+   (Key::MediaStop, "media-stop"),
+   (Key::Minus, "minus"),
+   (Key::Multiply, "multiply"),
+   (Key::Mute, "mute"),
+   (Key::MyComputer, "my-computer"),
+   (Key::NavigateForward, "navigate-forward"),
+   (Key::NavigateBackward, "navigate-backward"),
+   (Key::NextTrack, "next-track"),
+   (Key::NoConvert, "no-convert"),
+   (Key::NumpadComma, "numpad-comma"),
+   (Key::NumpadEnter, "numpad-enter"),
+   (Key::NumpadEquals, "numpad-equals"),
+   (Key::OEM102, "oem102"),
+   (Key::Period, "period"),
+   (Key::PlayPause, "play-pause"),
+   (Key::Power, "power"),
+   (Key::PrevTrack, "prev-track"),
+   (Key::RAlt, "ralt"),
+   (Key::RBracket, "rbracket"),
+   (Key::RControl, "rcontrol"),
+   (Key::RMenu, "rmenu"),
+   (Key::RShift, "rshift"),
+   (Key::RWin, "rwin"),
+   (Key::Semicolon, "semicolon"),
+   (Key::Slash, "slash"),
+   (Key::Sleep, "sleep"),
+   (Key::Stop, "stop"),
+   (Key::Subtract, "subtract"),
+   (Key::Sysrq, "sysrq"),
+   (Key::Tab, "tab"),
+   (Key::Underline, "underline"),
+   (Key::Unlabeled, "unlabeled"),
+   (Key::VolumeDown, "volume-down"),
+   (Key::VolumeUp, "volume-up"),
+   (Key::Wake, "wake"),
+   (Key::WebBack, "web-back"),
+   (Key::WebFavorites, "web-favorites"),
+   (Key::WebForward, "web-forward"),
+   (Key::WebHome, "web-home"),
+   (Key::WebRefresh, "web-refresh"),
+   (Key::WebSearch, "web-search"),
+   (Key::WebStop, "web-stop"),
+   (Key::Yen, "yen"),
+];
+
+impl Key {
+   /// Lower case name of the key, `page-down`, `lshift`, `f1` or `1` for the number keys.
+   pub fn name(&self) -> &'static str {
+      KEY_NAMES.iter().find(|&&(key, _)| key == *self).map(|&(_, name)| name).unwrap_or("")
+   }
+
+   /// Looks up a key by name, ignoring case.
+   pub fn from_name(name: &str) -> Option<Key> {
+      KEY_NAMES.iter().find(|&&(_, n)| n.eq_ignore_ascii_case(name)).map(|&(key, _)| key)
+   }
+}
+
+impl Mouse {
+   /// Lower case name of the button, `left`, `right`, `middle`, `x1` or `x2`.
+   pub fn name(&self) -> &'static str {
+      match *self {
+         Mouse::Left => "left",
+         Mouse::Right => "right",
+         Mouse::Middle => "middle",
+         Mouse::X1 => "x1",
+         Mouse::X2 => "x2",
+      }
+   }
+
+   /// Looks up a button by name, ignoring case.
+   pub fn from_name(name: &str) -> Option<Mouse> {
+      [Mouse::Left, Mouse::Right, Mouse::Middle, Mouse::X1, Mouse::X2].iter()
+         .find(|button| button.name().eq_ignore_ascii_case(name))
+         .cloned()
+   }
+}
+
+/// Modifier keys held down, either the left or the right key counts.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Modifiers {
+   pub shift: bool,
+   pub ctrl: bool,
+   pub alt: bool,
+   /// The super key, Windows or Command depending on the keyboard.
+   pub logo: bool,
+}
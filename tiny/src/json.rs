@@ -0,0 +1,293 @@
+use std::char;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Arrays and objects nested deeper than this are an error rather than a stack overflow.
+const MAX_DEPTH: usize = 128;
+
+/// A parsed JSON value, just enough to read files exported by other tools.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+   Null,
+   Bool(bool),
+   Number(f64),
+   String(String),
+   Array(Vec<Json>),
+   /// Members in the order they appear in the text.
+   Object(Vec<(String, Json)>),
+}
+
+impl Json {
+   pub fn parse(text: &str) -> Result<Json, String> {
+      let mut parser = Parser {
+         chars: text.chars().peekable(),
+         line: 1,
+         depth: 0,
+      };
+
+      let value = parser.value()?;
+      parser.skip_whitespace();
+
+      match parser.chars.next() {
+         None => Ok(value),
+         Some(ch) => Err(parser.error(&format!("unexpected '{}' after the value", ch))),
+      }
+   }
+
+   /// The member called `key`, if this is an object that has one.
+   pub fn get(&self, key: &str) -> Option<&Json> {
+      match *self {
+         Json::Object(ref members) => members.iter().find(|member| member.0 == key).map(|member| &member.1),
+         _ => None,
+      }
+   }
+
+   pub fn as_f64(&self) -> Option<f64> {
+      match *self {
+         Json::Number(number) => Some(number),
+         _ => None,
+      }
+   }
+
+   pub fn as_i32(&self) -> Option<i32> {
+      self.as_f64().map(|number| number as i32)
+   }
+
+   pub fn as_str(&self) -> Option<&str> {
+      match *self {
+         Json::String(ref string) => Some(string),
+         _ => None,
+      }
+   }
+
+   pub fn as_array(&self) -> Option<&[Json]> {
+      match *self {
+         Json::Array(ref values) => Some(values),
+         _ => None,
+      }
+   }
+
+   pub fn as_object(&self) -> Option<&[(String, Json)]> {
+      match *self {
+         Json::Object(ref members) => Some(members),
+         _ => None,
+      }
+   }
+}
+
+struct Parser<'a> {
+   chars: Peekable<Chars<'a>>,
+   line: usize,
+   depth: usize,
+}
+
+impl<'a> Parser<'a> {
+   fn error(&self, message: &str) -> String {
+      format!("Line {}: {}", self.line, message)
+   }
+
+   fn skip_whitespace(&mut self) {
+      while let Some(&ch) = self.chars.peek() {
+         if !ch.is_whitespace() {
+            break;
+         }
+
+         if ch == '\n' {
+            self.line += 1;
+         }
+
+         self.chars.next();
+      }
+   }
+
+   fn expect(&mut self, expected: char) -> Result<(), String> {
+      self.skip_whitespace();
+
+      match self.chars.next() {
+         Some(ch) if ch == expected => Ok(()),
+         Some(ch) => Err(self.error(&format!("expected '{}' but found '{}'", expected, ch))),
+         None => Err(self.error(&format!("expected '{}' but the text ended", expected))),
+      }
+   }
+
+   fn value(&mut self) -> Result<Json, String> {
+      self.skip_whitespace();
+
+      match self.chars.peek().cloned() {
+         Some(ch) if ch == '{' || ch == '[' => {
+            if self.depth == MAX_DEPTH {
+               return Err(self.error(&format!("nested deeper than {} levels", MAX_DEPTH)));
+            }
+
+            self.depth += 1;
+            let value = if ch == '{' { self.object() } else { self.array() };
+            self.depth -= 1;
+            value
+         },
+         Some('"') => self.string().map(Json::String),
+         Some(ch) if ch == '-' || ch.is_ascii_digit() => self.number(),
+         Some(ch) if ch.is_ascii_alphabetic() => {
+            let mut word = String::new();
+            while let Some(ch) = self.chars.peek().cloned().filter(char::is_ascii_alphabetic) {
+               word.push(ch);
+               self.chars.next();
+            }
+
+            match word.as_str() {
+               "null" => Ok(Json::Null),
+               "true" => Ok(Json::Bool(true)),
+               "false" => Ok(Json::Bool(false)),
+               _ => Err(self.error(&format!("unknown value '{}'", word))),
+            }
+         },
+         Some(ch) => Err(self.error(&format!("unexpected '{}'", ch))),
+         None => Err(self.error("expected a value but the text ended")),
+      }
+   }
+
+   fn object(&mut self) -> Result<Json, String> {
+      self.expect('{')?;
+      let mut members = Vec::new();
+
+      self.skip_whitespace();
+      if self.chars.peek() == Some(&'}') {
+         self.chars.next();
+         return Ok(Json::Object(members));
+      }
+
+      loop {
+         self.skip_whitespace();
+         let key = self.string()?;
+         self.expect(':')?;
+         members.push((key, self.value()?));
+
+         self.skip_whitespace();
+         match self.chars.next() {
+            Some(',') => continue,
+            Some('}') => return Ok(Json::Object(members)),
+            _ => return Err(self.error("expected ',' or '}' after an object member")),
+         }
+      }
+   }
+
+   fn array(&mut self) -> Result<Json, String> {
+      self.expect('[')?;
+      let mut values = Vec::new();
+
+      self.skip_whitespace();
+      if self.chars.peek() == Some(&']') {
+         self.chars.next();
+         return Ok(Json::Array(values));
+      }
+
+      loop {
+         values.push(self.value()?);
+
+         self.skip_whitespace();
+         match self.chars.next() {
+            Some(',') => continue,
+            Some(']') => return Ok(Json::Array(values)),
+            _ => return Err(self.error("expected ',' or ']' after an array value")),
+         }
+      }
+   }
+
+   fn string(&mut self) -> Result<String, String> {
+      self.expect('"')?;
+      let mut string = String::new();
+
+      loop {
+         match self.chars.next() {
+            Some('"') => return Ok(string),
+            Some('\\') => {
+               let ch = match self.chars.next() {
+                  Some('n') => '\n',
+                  Some('t') => '\t',
+                  Some('r') => '\r',
+                  Some('b') => '\u{8}',
+                  Some('f') => '\u{c}',
+                  Some('u') => self.unicode_escape()?,
+                  Some(ch) => ch,
+                  None => break,
+               };
+
+               string.push(ch);
+            },
+            Some(ch) => string.push(ch),
+            None => break,
+         }
+      }
+
+      Err(self.error("unterminated string"))
+   }
+
+   /// The character after `\u`, joining UTF-16 surrogate pairs.
+   fn unicode_escape(&mut self) -> Result<char, String> {
+      let high = self.hex4()?;
+      if !(0xD800..0xDC00).contains(&high) {
+         return char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"));
+      }
+
+      if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+         return Err(self.error("unpaired surrogate in unicode escape"));
+      }
+
+      let low = self.hex4()?;
+      if !(0xDC00..0xE000).contains(&low) {
+         return Err(self.error("unpaired surrogate in unicode escape"));
+      }
+
+      char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+         .ok_or_else(|| self.error("invalid unicode escape"))
+   }
+
+   fn hex4(&mut self) -> Result<u32, String> {
+      let digits = self.chars.by_ref().take(4).collect::<String>();
+
+      match u32::from_str_radix(&digits, 16) {
+         Ok(value) if digits.len() == 4 => Ok(value),
+         _ => Err(self.error(&format!("invalid unicode escape '{}'", digits))),
+      }
+   }
+
+   fn number(&mut self) -> Result<Json, String> {
+      let mut text = String::new();
+      while let Some(ch) = self.chars.peek().cloned() {
+         if !(ch.is_ascii_digit() || ch == '-' || ch == '+' || ch == '.' || ch == 'e' || ch == 'E') {
+            break;
+         }
+
+         text.push(ch);
+         self.chars.next();
+      }
+
+      match text.parse::<f64>() {
+         Ok(number) => Ok(Json::Number(number)),
+         Err(_) => Err(self.error(&format!("invalid number '{}'", text))),
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parse() {
+      let json = Json::parse("{\"a\": [1, -2.5e1, true, null],\n \"b\": {\"c\": \"x\\\"\\u00e9\\ud83d\\ude00\"}, \"d\": {}}").unwrap();
+
+      assert_eq!(json.get("a"), Some(&Json::Array(vec![Json::Number(1.0), Json::Number(-25.0), Json::Bool(true), Json::Null])));
+      assert_eq!(json.get("b").and_then(|b| b.get("c")).and_then(Json::as_str), Some("x\"é\u{1F600}"));
+      assert_eq!(json.get("d").and_then(Json::as_object).map(|d| d.len()), Some(0));
+      assert_eq!(json.get("e"), None);
+
+      assert_eq!(Json::parse("[1,\n 2,]"), Err(String::from("Line 2: unexpected ']'")));
+      assert_eq!(Json::parse("{} x"), Err(String::from("Line 1: unexpected 'x' after the value")));
+      assert_eq!(Json::parse("\"\\ud83d\\u0041\""), Err(String::from("Line 1: unpaired surrogate in unicode escape")));
+
+      // Deep nesting fails instead of overflowing the stack
+      assert!(Json::parse(&format!("{}{}", "[".repeat(128), "]".repeat(128))).is_ok());
+      assert_eq!(Json::parse(&"[{\"a\":".repeat(100000)), Err(String::from("Line 1: nested deeper than 128 levels")));
+   }
+}
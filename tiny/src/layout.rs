@@ -0,0 +1,353 @@
+use super::*;
+
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Align {
+   Left,
+   Center,
+   Right,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VAlign {
+   Top,
+   Middle,
+   Bottom,
+}
+
+/// Where and how `Font::layout` places text, built with the `with_*` methods.
+///
+/// By default text is placed at the top left of `rect`, wrapped at its width and allowed
+/// to run past its bottom.
+#[derive(Copy, Clone, PartialEq)]
+pub struct TextBox {
+   pub rect: Rect,
+   pub align: Align,
+   pub valign: VAlign,
+   pub wrap: bool,
+   pub ellipsis: bool,
+}
+
+impl TextBox {
+   pub fn new(rect: Rect) -> TextBox {
+      TextBox {
+         rect: rect,
+         align: Align::Left,
+         valign: VAlign::Top,
+         wrap: true,
+         ellipsis: false,
+      }
+   }
+
+   pub fn with_align(mut self, align: Align) -> TextBox {
+      self.align = align;
+      self
+   }
+
+   pub fn with_valign(mut self, valign: VAlign) -> TextBox {
+      self.valign = valign;
+      self
+   }
+
+   /// Breaks lines between words, or inside words longer than the box, to fit the width.
+   pub fn with_wrap(mut self, wrap: bool) -> TextBox {
+      self.wrap = wrap;
+      self
+   }
+
+   /// Drops lines that don't fit the height and shortens lines that don't fit the width,
+   /// ending the last line shown with an ellipsis.
+   pub fn with_ellipsis(mut self, ellipsis: bool) -> TextBox {
+      self.ellipsis = ellipsis;
+      self
+   }
+}
+
+
+/// A line of laid out text.
+#[derive(Clone, PartialEq)]
+pub struct Line {
+   /// Byte range of the text shown on this line, not including the ellipsis.
+   pub range: Range<usize>,
+   /// Where the line is drawn, `char_height` high.
+   pub rect: Rect,
+   /// Whether the line ends in an ellipsis because text was cut off.
+   pub ellipsis: bool,
+}
+
+/// Text placed in a `TextBox` by `Font::layout`, draw it with `Painter::text_layout`.
+#[derive(Clone)]
+pub struct Layout {
+   pub lines: Vec<Line>,
+   /// Bounds of all lines together.
+   pub rect: Rect,
+   ellipsis: &'static str,
+}
+
+impl Layout {
+   /// The text drawn in place of cut off text.
+   pub fn ellipsis(&self) -> &'static str {
+      self.ellipsis
+   }
+
+   /// Index of the line at height `y`, lines include the spacing below them.
+   pub fn line_at(&self, y: i32, font: &Font) -> Option<usize> {
+      self.lines.iter().position(|line| y >= line.rect.top && y < line.rect.top + cmp::max(font.line_height, font.char_height))
+   }
+
+   /// Byte index in `text` of the character under `x`, `y`. Points left or right of a
+   /// line give its first character or the end of the line. `None` when the line doesn't
+   /// fit in `text`, which should be the text the layout was made from.
+   pub fn index_at(&self, x: i32, y: i32, text: &str, font: &Font) -> Option<usize> {
+      let line = &self.lines[self.line_at(y, font)?];
+
+      let mut left = line.rect.left;
+      for (range, markup) in parse_markup(text.get(line.range.clone())?) {
+         let advance = match markup {
+            Markup::Char(ch) => font.advance(ch),
+            Markup::Color(_) => continue,
+         };
+
+         if x < left + advance {
+            return Some(line.range.start + range.start);
+         }
+
+         left += advance;
+      }
+
+      Some(line.range.end)
+   }
+}
+
+
+impl Font {
+   /// Breaks `text` into lines and places them in `text_box`.
+   pub fn layout(&self, text: &str, text_box: &TextBox) -> Layout {
+      let ellipsis = if self.has_glyph('\u{2026}') { "\u{2026}" } else { "..." };
+      let width = text_box.rect.width();
+      let wrap_width = if text_box.wrap { Some(width) } else { None };
+
+      let mut ranges = Vec::new();
+      let mut start = 0;
+      for paragraph in text.split('\n') {
+         self.wrap(text, start..start + paragraph.len(), wrap_width, &mut ranges);
+         start += paragraph.len() + 1;
+      }
+
+      // Lines that don't fit are dropped, the last one shown is shortened to make room
+      let mut cut = vec![false; ranges.len()];
+      if text_box.ellipsis {
+         let height = text_box.rect.height();
+         let fit = if height < self.char_height { 1 } else { 1 + (height - self.char_height) / cmp::max(self.line_height, 1) };
+
+         if ranges.len() > fit as usize {
+            ranges.truncate(fit as usize);
+            cut.truncate(fit as usize);
+            cut[fit as usize - 1] = true;
+         }
+
+         let ellipsis_width = self.measure(ellipsis).width();
+         for (range, cut) in ranges.iter_mut().zip(cut.iter_mut()) {
+            if !*cut && self.measure(&text[range.clone()]).width() <= width {
+               continue;
+            }
+
+            *cut = true;
+            while range.end > range.start && self.measure(&text[range.clone()]).width() + ellipsis_width > width {
+               range.end = prev_char(text, range.clone());
+            }
+         }
+      }
+
+      let count = ranges.len() as i32;
+      let height = if count > 0 { self.char_height + (count - 1) * self.line_height } else { 0 };
+      let top = text_box.rect.top + match text_box.valign {
+         VAlign::Top => 0,
+         VAlign::Middle => (text_box.rect.height() - height) / 2,
+         VAlign::Bottom => text_box.rect.height() - height,
+      };
+
+      let mut lines = Vec::new();
+      let mut bounds: Option<Rect> = None;
+      for (idx, (range, ellipsis_shown)) in ranges.into_iter().zip(cut).enumerate() {
+         let mut line_width = self.measure(&text[range.clone()]).width();
+         if ellipsis_shown {
+            line_width += self.measure(ellipsis).width();
+         }
+
+         let left = text_box.rect.left + match text_box.align {
+            Align::Left => 0,
+            Align::Center => (width - line_width) / 2,
+            Align::Right => width - line_width,
+         };
+
+         let rect = Rect::new_size(left, top + idx as i32 * self.line_height, line_width, self.char_height);
+         bounds = Some(match bounds {
+            Some(b) => Rect::new(cmp::min(b.left, rect.left), b.top, cmp::max(b.right, rect.right), rect.bottom),
+            None => rect,
+         });
+
+         lines.push(Line {
+            range: range,
+            rect: rect,
+            ellipsis: ellipsis_shown,
+         });
+      }
+
+      Layout {
+         lines: lines,
+         rect: bounds.unwrap_or_else(|| Rect::new_size(text_box.rect.left, top, 0, 0)),
+         ellipsis: ellipsis,
+      }
+   }
+
+   /// Splits a paragraph into lines no wider than `width`, spaces at the breaks are dropped.
+   fn wrap(&self, text: &str, paragraph: Range<usize>, width: Option<i32>, lines: &mut Vec<Range<usize>>) {
+      let width = match width {
+         Some(width) => width,
+         None => return lines.push(paragraph),
+      };
+
+      let mut start = paragraph.start;
+      loop {
+         // Find the last break that fits, or else as many characters as fit
+         let mut line_width = 0;
+         let mut last_break = None;
+         let mut word_seen = false;
+         let mut end = paragraph.end;
+
+         for (range, markup) in parse_markup(&text[start..paragraph.end]) {
+            let (idx, ch) = match markup {
+               Markup::Char(ch) => (start + range.start, ch),
+               Markup::Color(_) => continue,
+            };
+
+            // Spaces before the first word aren't breaks, they would leave an empty line
+            if ch != ' ' {
+               word_seen = true;
+            } else if word_seen {
+               last_break = Some(idx);
+            }
+
+            line_width += self.advance(ch);
+            if line_width > width && ch != ' ' {
+               end = match last_break {
+                  Some(space) => space,
+                  None if idx > start => idx,
+                  None => start + range.end,
+               };
+               break;
+            }
+         }
+
+         let line_end = text[start..end].trim_end_matches(' ').len() + start;
+         lines.push(start..line_end);
+
+         if end >= paragraph.end {
+            break;
+         }
+
+         start = end + text[end..paragraph.end].len() - text[end..paragraph.end].trim_start_matches(' ').len();
+         if start >= paragraph.end {
+            break;
+         }
+      }
+   }
+}
+
+/// Start of the last character or colour tag in `range`.
+fn prev_char(text: &str, range: Range<usize>) -> usize {
+   parse_markup(&text[range.clone()]).last().map_or(range.start, |(idx, _)| range.start + idx.start)
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn lines<'a>(text: &'a str, layout: &Layout) -> Vec<&'a str> {
+      layout.lines.iter().map(|line| &text[line.range.clone()]).collect()
+   }
+
+   #[test]
+   fn wrap() {
+      let font = default_font::font_4x7();
+      let text = "Builds   barracks\n\nand a veryveryverylongword";
+      let layout = font.layout(text, &TextBox::new(Rect::new_size(0, 0, 40, 100)));
+
+      assert_eq!(lines(text, &layout), ["Builds", "barracks", "", "and a", "veryveryve", "rylongword"]);
+      assert!(layout.lines[1].rect == Rect::new_size(0, font.line_height, 32, font.char_height));
+      assert!(layout.rect == Rect::new_size(0, 0, 40, font.char_height + 5 * font.line_height));
+
+      let unwrapped = font.layout(text, &TextBox::new(Rect::new_size(0, 0, 40, 100)).with_wrap(false));
+      assert_eq!(lines(text, &unwrapped), ["Builds   barracks", "", "and a veryveryverylongword"]);
+
+      // Leading spaces stay with the first word, even when it has to be broken
+      let indented = font.layout("  barracks", &TextBox::new(Rect::new_size(0, 0, 32, 100)));
+      assert_eq!(lines("  barracks", &indented), ["  barrac", "ks"]);
+
+      let cut = font.layout(" barracks", &TextBox::new(Rect::new_size(0, 0, 32, 7)).with_ellipsis(true));
+      assert_eq!(lines(" barracks", &cut), [" barr"]);
+   }
+
+   #[test]
+   fn align_and_ellipsis() {
+      let font = default_font::font_4x7();
+      let text = "one two three four five";
+      let text_box = TextBox::new(Rect::new(10, 20, 50, 44))
+         .with_align(Align::Right)
+         .with_valign(VAlign::Bottom)
+         .with_ellipsis(true);
+
+      // Two lines fit the height, the second is cut short to make room for the ellipsis
+      let layout = font.layout(text, &text_box);
+      assert_eq!(lines(text, &layout), ["one two", "three f"]);
+      assert_eq!(layout.ellipsis(), "...");
+      assert!(!layout.lines[0].ellipsis && layout.lines[1].ellipsis);
+      assert!(layout.lines[0].rect == Rect::new_size(22, 28, 28, 7));
+      assert!(layout.lines[1].rect == Rect::new_size(10, 37, 40, 7));
+      assert!(layout.rect == Rect::new(10, 28, 50, 44));
+
+      let centered = font.layout("ab", &text_box.with_align(Align::Center).with_valign(VAlign::Middle));
+      assert!(centered.rect == Rect::new_size(26, 28, 8, 7));
+
+      let single = font.layout("a long line", &TextBox::new(Rect::new_size(0, 0, 24, 2)).with_wrap(false).with_ellipsis(true));
+      assert_eq!(lines("a long line", &single), ["a l"]);
+      assert_eq!(single.lines[0].rect.width(), 24);
+   }
+
+   #[test]
+   fn hit_testing() {
+      let font = default_font::font_4x7();
+      let text = "gold: 12\nwood: 3";
+      let layout = font.layout(text, &TextBox::new(Rect::new_size(4, 4, 100, 100)));
+
+      assert_eq!(layout.line_at(3, &font), None);
+      assert_eq!(layout.line_at(4, &font), Some(0));
+      assert_eq!(layout.line_at(4 + font.line_height, &font), Some(1));
+      assert_eq!(layout.index_at(4, 4, text, &font), Some(0));
+      assert_eq!(layout.index_at(4 + 4 * 4 + 1, 4, text, &font), Some(4));
+      assert_eq!(layout.index_at(90, 4 + font.line_height, text, &font), Some(text.len()));
+      assert_eq!(layout.index_at(-5, 4 + font.line_height, text, &font), Some(9));
+
+      // Text the layout wasn't made from doesn't panic
+      assert_eq!(layout.index_at(4, 4 + font.line_height, "gold", &font), None);
+      assert_eq!(layout.index_at(4, 4, "gold: 1\u{20ac}", &font), None);
+   }
+
+   #[test]
+   fn markup() {
+      let font = default_font::font_4x7();
+      let text = "{mandy}one{} two {{ab}";
+      let layout = font.layout(text, &TextBox::new(Rect::new_size(0, 0, 20, 100)));
+
+      assert_eq!(lines(text, &layout), ["{mandy}one{}", "two", "{{ab}"]);
+      assert_eq!(layout.lines[0].rect.width(), 12);
+      assert_eq!(layout.lines[2].rect.width(), 16);
+      assert_eq!(layout.index_at(5, 0, text, &font), Some(8));
+
+      let cut = font.layout("{19}abcdef", &TextBox::new(Rect::new_size(0, 0, 20, 7)).with_ellipsis(true));
+      assert_eq!(lines("{19}abcdef", &cut), ["{19}ab"]);
+   }
+}
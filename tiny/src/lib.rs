@@ -1,5 +1,7 @@
 extern crate libc;
 extern crate image;
+extern crate png;
+extern crate gif;
 
 #[cfg(target_os = "windows")]
 extern crate winapi;
@@ -13,13 +15,41 @@ mod platform;
 pub mod palette;
 pub mod default_font;
 mod font;
+mod font_files;
+mod layout;
+mod markup;
+mod json;
+mod sprite;
+mod nine_slice;
+mod canvas;
 mod input;
+mod headless;
+mod snapshot;
+mod blend;
+mod recorder;
+mod config;
+mod actions;
+mod replay;
 
 pub use bitmap::*;
 pub use font::*;
+pub use layout::*;
+pub use markup::*;
+pub use sprite::*;
+pub use nine_slice::*;
+pub use canvas::*;
 pub use input::*;
-
-use std::cell::RefCell;
+pub use headless::*;
+pub use snapshot::*;
+pub use blend::*;
+pub use recorder::*;
+pub use config::*;
+pub use actions::*;
+pub use replay::*;
+
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::result::Result;
 use std::cmp;
 use std::time::{Instant, Duration};
@@ -65,7 +95,7 @@ impl Rect {
            left: cmp::min(r.right, cmp::max(self.left, r.left)),
            right: cmp::max(r.left, cmp::min(self.right, r.right)),
            top: cmp::min(r.bottom, cmp::max(self.top, r.top)),
-           bottom: cmp::max(r.top, cmp::max(self.bottom, r.bottom)),
+           bottom: cmp::max(r.top, cmp::min(self.bottom, r.bottom)),
        }
    }
 
@@ -171,41 +201,90 @@ impl Color {
 #[derive(Clone)]
 pub struct Palette {
    colors: Vec<Color>,
+   names: Vec<String>,
 }
 
 impl Palette {
    fn new() -> Palette {
       Palette {
          colors: vec![Color::new(0, 0, 0, 0), Color::new(0, 0, 0, 255), Color::new(255, 255, 255, 255)],
+         names: vec![String::from("Transparent"), String::from("Black"), String::from("White")],
       }
    }
 
-   fn add_color(&mut self, color: Color) -> u8 {
-      for i in 0..self.colors.len() {
-         if self.colors[i].rgba == color.rgba {
-            return i as u8;
-         }
+   fn add_color(&mut self, color: Color) -> Option<u8> {
+      if let Some(idx) = self.index_of(color) {
+         return Some(idx);
+      }
+
+      if self.colors.len() >= 256 {
+         return None;
       }
 
       self.colors.push(color);
-      (self.colors.len() - 1) as u8
+      self.names.push(String::new());
+      Some((self.colors.len() - 1) as u8)
+   }
+
+   fn index_of(&self, color: Color) -> Option<u8> {
+      self.colors.iter().position(|c| c.rgba == color.rgba).map(|idx| idx as u8)
    }
-}
 
-pub struct Config {
-   title: String,
-   width: u32,
-   height: u32,
-   scale: u32,
+   pub fn colors(&self) -> &Vec<Color> {
+      &self.colors
+   }
+
+   /// Colour names, in the same order as the colours. Unnamed colours have an empty name.
+   pub fn names(&self) -> &Vec<String> {
+      &self.names
+   }
+
+   pub fn name(&self, index: u8) -> Option<&str> {
+      self.names.get(index as usize).map(|name| name.as_str())
+   }
+
+   /// Looks up a colour by name, ignoring case.
+   pub fn find(&self, name: &str) -> Option<u8> {
+      let name = name.to_lowercase();
+      self.names.iter().position(|n| !n.is_empty() && n.to_lowercase() == name).map(|idx| idx as u8)
+   }
 }
 
 pub const DRAW_FLIP_H: u32 = (1 << 1);
 pub const DRAW_MASK: u32 = (1 << 2);
+pub const DRAW_FLIP_V: u32 = (1 << 3);
+pub const DRAW_ROTATE_90: u32 = (1 << 4);
+pub const DRAW_ROTATE_180: u32 = (1 << 5);
+pub const DRAW_ROTATE_270: u32 = DRAW_ROTATE_90 | DRAW_ROTATE_180;
 
 pub trait Painter {
    fn size(&self) -> (u32, u32);
 
+   /// Limits drawing to `rect`, given in the current translation, within the clip rect of the
+   /// last `push_clip`. `None` goes back to that clip rect, or the whole target without one.
    fn clip(&self, rect: Option<Rect>);
+   /// Limits drawing to `rect` within the current clip rect until the matching `pop_clip`,
+   /// which restores the clip rect from before.
+   fn push_clip(&self, rect: Rect);
+   fn pop_clip(&self);
+
+   /// Moves everything drawn from now on by `x`, `y` on top of the current translation,
+   /// so nested widgets can draw in their own coordinates.
+   fn push_translation(&self, x: i32, y: i32);
+   /// Goes back to the translation before the last `push_translation`.
+   fn pop_translation(&self);
+   /// Where the point 0, 0 ends up on the target.
+   fn translation(&self) -> (i32, i32);
+
+   /// Sets the blend table used for every pixel written from now on, `None` writes colours as is.
+   fn blend(&self, table: Option<Rc<BlendTable>>);
+
+   /// Sets the palette colour names in text markup are looked up in, see `parse_markup`.
+   ///
+   /// The painter passed to `Application::paint` gets the context palette as it was when the
+   /// frame started. Colours added during `paint`, with `Context::palette_add` or by loading
+   /// a bitmap, can only be found by name from the next frame on.
+   fn palette(&self, palette: Option<Rc<Palette>>);
 
    fn clear(&self, color: u8);
 
@@ -216,17 +295,50 @@ pub trait Painter {
    fn rect_stroke(&self, rect: Rect, color: u8);
    fn rect_fill(&self, rect: Rect, color: u8);
 
-   fn blit(&self, x: i32, y: i32, source: &Bitmap, source_rect: Rect, flags: u32, color: u8);
+   fn circle_stroke(&self, x: i32, y: i32, radius: i32, color: u8);
+   fn circle_fill(&self, x: i32, y: i32, radius: i32, color: u8);
 
+   fn ellipse_stroke(&self, x: i32, y: i32, radius_x: i32, radius_y: i32, color: u8);
+   fn ellipse_fill(&self, x: i32, y: i32, radius_x: i32, radius_y: i32, color: u8);
+
+   fn triangle_stroke(&self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32, color: u8);
+   fn triangle_fill(&self, x0: i32, y0: i32, x1: i32, y1: i32, x2: i32, y2: i32, color: u8);
+
+   /// Draws the closed outline through `points`, the last point connects back to the first.
+   fn polygon_stroke(&self, points: &[(i32, i32)], color: u8);
+   /// Fills a convex or concave polygon using the even-odd rule, the outline is included.
+   fn polygon_fill(&self, points: &[(i32, i32)], color: u8);
+
+   fn blit(&self, x: i32, y: i32, source: &Bitmap, source_rect: Rect, flags: u32, color: u8);
+   /// Stretches `source_rect` over `dest_rect` using nearest-neighbour sampling.
+   fn blit_scaled(&self, dest_rect: Rect, source: &Bitmap, source_rect: Rect, flags: u32, color: u8);
+   /// Draws `source_rect` rotated clockwise by `angle` radians, with the pivot (relative to
+   /// `source_rect`) placed at `x`, `y`.
+   fn blit_rotated(&self, x: i32, y: i32, source: &Bitmap, source_rect: Rect, pivot_x: f32, pivot_y: f32, angle: f32, flags: u32, color: u8);
+   /// Draws the parts of `slice` over `dest_rect`. `flags` apply to every part, so only
+   /// `DRAW_MASK` is of much use.
+   fn nine_slice(&self, dest_rect: Rect, source: &Bitmap, slice: &NineSlice, flags: u32, color: u8);
+
+   /// Draws `text` starting in `color`, colour tags in the text switch colour as they are reached.
    fn text(&self, x: i32, y: i32, text: &str, color: u8, font: &Font);
    fn char(&self, x: i32, y: i32, ch: char, color: u8, font: &Font) -> (i32, i32);
+   /// Draws `text` as placed by `Font::layout`. Lines that don't fit in `text`, because it
+   /// isn't the text the layout was made from, are skipped.
+   fn text_layout(&self, text: &str, layout: &Layout, color: u8, font: &Font);
 }
 
 pub trait Application : Sized {
+   /// Adjusts the configuration passed to `run` before the window is created.
+   fn configure(config: Config) -> Config { config }
+
    fn new(ctx: &mut Context) -> Result<Self, String>;
 
+   /// Advances the simulation by one fixed step, see `Timestep`.
    fn step(&mut self, ctx: &Context) -> bool { !ctx.key_pressed(Key::Escape) }
-   fn paint(&self, ctx: &Context, painter: &Painter);
+
+   /// Paints a frame. `alpha` is how far the frame is between the last step and the next,
+   /// in the range 0-1, use it to interpolate movement.
+   fn paint(&self, ctx: &Context, painter: &Painter, alpha: f32);
 }
 
 
@@ -240,8 +352,22 @@ pub const WHITE: u8 = 2;
 
 
 pub struct Context {
-   palette: RefCell<Palette>,
+   config: Config,
+   /// Shared with the painter every frame, changes copy it if a painter still holds it.
+   palette: RefCell<Rc<Palette>>,
    window: platform::Window,
+   screenshots: RefCell<Vec<PathBuf>>,
+   screenshot_count: Cell<u32>,
+   recorder: RefCell<Option<GifRecorder>>,
+   recording_count: Cell<u32>,
+   input_recorder: RefCell<Option<InputRecorder>>,
+   input_playback: RefCell<Option<InputPlayback>>,
+   timestep: Timestep,
+   lag: f64,
+   steps: u64,
+   key_repeat: KeyRepeat,
+   key_held: [u32; 256],
+   actions: Actions,
 
    pub frame_time: f64,
    pub step_time: f64,
@@ -251,67 +377,305 @@ pub struct Context {
 }
 
 impl Context {
-   fn new(window: platform::Window) -> Context {
-      Context {
-         palette: RefCell::new(Palette::new()),
+   fn new(config: Config) -> Result<Context, String> {
+      let window = platform::Window::new(&config)?;
+      let palette = config.palette().cloned().unwrap_or_else(Palette::new);
+      let timestep = config.timestep();
+      let key_repeat = config.key_repeat();
+
+      let input_recorder = match config.input_recording() {
+         Some(path) => Some(InputRecorder::new(path, config.width(), config.height())?),
+         None => None,
+      };
+
+      let input_playback = match config.input_playback() {
+         Some(path) => Some(InputPlayback::load(path, config.width(), config.height())?),
+         None => None,
+      };
+
+      Ok(Context {
+         config: config,
+         palette: RefCell::new(Rc::new(palette)),
          window: window,
+         screenshots: RefCell::new(Vec::new()),
+         screenshot_count: Cell::new(0),
+         recorder: RefCell::new(None),
+         recording_count: Cell::new(0),
+         input_recorder: RefCell::new(input_recorder),
+         input_playback: RefCell::new(input_playback),
+         timestep: timestep,
+         lag: 0.0,
+         steps: 0,
+         key_repeat: key_repeat,
+         key_held: [0; 256],
+         actions: Actions::new(),
          frame_time: 0.0,
          step_time: 0.0,
          paint_time: 0.0,
          blit_time: 0.0,
          sleep_time: 0.0,
-      }
+      })
+   }
+
+   pub fn config(&self) -> &Config {
+      &self.config
+   }
+
+   /// Prints `text` if the configured log level includes `level`.
+   pub fn log(&self, level: LogLevel, text: &str) {
+      self.config.log(level, text);
+   }
+
+   pub fn timestep(&self) -> Timestep {
+      self.timestep
+   }
+
+   pub fn set_timestep(&mut self, timestep: Timestep) {
+      self.timestep = timestep;
+   }
+
+   /// Number of fixed steps run so far.
+   pub fn steps(&self) -> u64 {
+      self.steps
    }
 
    pub fn set_palette(&mut self, palette: Palette) {
-      *self.palette.borrow_mut() = palette;
+      *self.palette.borrow_mut() = Rc::new(palette);
    }
 
    pub fn palette(&self) -> Palette {
-      self.palette.borrow().clone()
+      Palette::clone(&self.palette.borrow())
+   }
+
+   /// Adds a colour to the palette, or finds it if it's already there. Fails when the palette is full.
+   pub fn palette_add(&self, color: Color) -> Result<u8, String> {
+       match Rc::make_mut(&mut self.palette.borrow_mut()).add_color(color) {
+          Some(idx) => Ok(idx),
+          None => Err(String::from("Palette is full")),
+       }
+   }
+
+   /// Saves the canvas as an RGBA PNG once the current frame has been painted.
+   pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) {
+      self.screenshots.borrow_mut().push(path.as_ref().to_path_buf());
+   }
+
+   /// Saves the canvas to the next free `screenshot-NNNN.png` in the working directory
+   /// once the current frame has been painted. Returns the chosen path.
+   pub fn screenshot(&self) -> PathBuf {
+      let path = next_free_path("screenshot", "png", &self.screenshot_count);
+      self.save_screenshot(&path);
+      path
+   }
+
+   /// Starts recording every painted frame to an animated GIF, replacing any running recording.
+   pub fn start_recording<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+      self.stop_recording()?;
+      *self.recorder.borrow_mut() = Some(GifRecorder::new(path)?);
+      Ok(())
+   }
+
+   /// Starts recording to the next free `capture-NNNN.gif` in the working directory.
+   pub fn record(&self) -> Result<PathBuf, String> {
+      let path = next_free_path("capture", "gif", &self.recording_count);
+      self.start_recording(&path)?;
+      Ok(path)
+   }
+
+   /// Finishes the running recording, if any.
+   pub fn stop_recording(&self) -> Result<(), String> {
+      match self.recorder.borrow_mut().take() {
+         Some(recorder) => recorder.finish(),
+         None => Ok(()),
+      }
+   }
+
+   pub fn recording(&self) -> bool {
+      self.recorder.borrow().is_some()
+   }
+
+   /// Starts recording the input of every frame from the next one on, replacing any running
+   /// input recording. Play it back with `start_input_playback` or `Headless::replay`.
+   pub fn start_input_recording<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+      self.stop_input_recording()?;
+      *self.input_recorder.borrow_mut() = Some(InputRecorder::new(path, self.config.width(), self.config.height())?);
+      Ok(())
+   }
+
+   pub fn stop_input_recording(&self) -> Result<(), String> {
+      match self.input_recorder.borrow_mut().take() {
+         Some(recorder) => recorder.finish(),
+         None => Ok(()),
+      }
+   }
+
+   pub fn recording_input(&self) -> bool {
+      self.input_recorder.borrow().is_some()
+   }
+
+   /// Replaces the input and frame times with a recording from the next frame on,
+   /// live input takes over again once it has played.
+   pub fn start_input_playback<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+      *self.input_playback.borrow_mut() = Some(InputPlayback::load(path, self.config.width(), self.config.height())?);
+      Ok(())
+   }
+
+   pub fn stop_input_playback(&self) {
+      *self.input_playback.borrow_mut() = None;
    }
 
-   pub fn palette_add(&self, color: Color) -> u8 {
-       self.palette.borrow_mut().add_color(color)
+   pub fn playing_input(&self) -> bool {
+      self.input_playback.borrow().is_some()
    }
 
    pub fn key_down(&self, key: Key) -> bool {
-       self.window.key_state[key as usize]
+       self.window.input().key_state[key as usize]
    }
 
    pub fn key_pressed(&self, key: Key) -> bool {
-       self.window.key_state[key as usize] && self.window.key_delta[key as usize]
+       let input = self.window.input();
+       input.key_state[key as usize] && input.key_delta[key as usize]
+   }
+
+   /// How long the key has been held in seconds, counted in steps from the step it was
+   /// pressed in. Zero when the key is up.
+   pub fn key_held(&self, key: Key) -> f32 {
+      match self.key_held[key as usize] {
+         0 => 0.0,
+         steps => ((steps - 1) as f64 * self.timestep.step_length()) as f32,
+      }
+   }
+
+   /// True on the step a key is pressed and then repeatedly while it is held, see `KeyRepeat`.
+   pub fn key_repeated(&self, key: Key) -> bool {
+      let steps = self.key_held[key as usize];
+      if steps <= 1 {
+         return steps == 1;
+      }
+
+      // Count the repeats up to this step and the previous one
+      let step_length = self.timestep.step_length();
+      let repeats = |steps: u32| {
+         let held = (steps - 1) as f64 * step_length - self.key_repeat.delay as f64;
+         if held < -1e-6 {
+            0
+         } else {
+            (held * self.key_repeat.rate as f64 + 1e-6).floor() as u64 + 1
+         }
+      };
+
+      repeats(steps) > repeats(steps - 1)
+   }
+
+   pub fn key_repeat(&self) -> KeyRepeat {
+      self.key_repeat
+   }
+
+   pub fn set_key_repeat(&mut self, key_repeat: KeyRepeat) {
+      self.key_repeat = key_repeat;
+   }
+
+   pub fn key_released(&self, key: Key) -> bool {
+       let input = self.window.input();
+       !input.key_state[key as usize] && input.key_delta[key as usize]
+   }
+
+   pub fn modifiers(&self) -> Modifiers {
+      let down = |left: Key, right: Key| self.key_down(left) || self.key_down(right);
+
+      Modifiers {
+         shift: down(Key::LShift, Key::RShift),
+         ctrl: down(Key::LControl, Key::RControl),
+         alt: down(Key::LAlt, Key::RAlt),
+         logo: down(Key::LWin, Key::RWin),
+      }
+   }
+
+   pub fn actions(&self) -> &Actions {
+      &self.actions
+   }
+
+   /// Replaces the bindings looked up by `action_down`, `action_pressed` and `action_released`.
+   pub fn set_actions(&mut self, actions: Actions) {
+      self.actions = actions;
+   }
+
+   /// True while any binding of `action` is held, unknown actions are never down.
+   pub fn action_down(&self, action: &str) -> bool {
+      self.actions.down(self, action)
+   }
+
+   pub fn action_pressed(&self, action: &str) -> bool {
+      self.actions.pressed(self, action)
+   }
+
+   pub fn action_released(&self, action: &str) -> bool {
+      self.actions.released(self, action)
    }
 
    pub fn text_input<'a>(&'a self) -> &'a Vec<char> {
-      &self.window.text_input
+      &self.window.input().text_input
    }
 
    pub fn mouse_down(&self, mouse: Mouse) -> bool {
-      self.window.mouse_state[mouse as usize]
+      self.window.input().mouse_state[mouse as usize]
    }
 
    pub fn mouse_pressed(&self, mouse: Mouse) -> bool {
-       self.window.mouse_state[mouse as usize] && self.window.mouse_delta[mouse as usize]
+       let input = self.window.input();
+       input.mouse_state[mouse as usize] && input.mouse_delta[mouse as usize]
+   }
+
+   pub fn mouse_released(&self, mouse: Mouse) -> bool {
+       let input = self.window.input();
+       !input.mouse_state[mouse as usize] && input.mouse_delta[mouse as usize]
    }
 
    pub fn mouse_position(&self) -> (u32, u32) {
-      (self.window.mouse_x, self.window.mouse_y)
+      let input = self.window.input();
+      (input.mouse_x, input.mouse_y)
+   }
+
+   /// How far the mouse moved since the previous step, in canvas pixels. Keeps counting
+   /// when the cursor is outside the canvas, where `mouse_position` stops at the edge.
+   pub fn mouse_motion(&self) -> (i32, i32) {
+      let input = self.window.input();
+      (input.mouse_dx, input.mouse_dy)
+   }
+
+   /// Wheel movement since the previous step in lines. Positive `y` is away from the user,
+   /// positive `x` is to the right.
+   pub fn mouse_wheel(&self) -> (f32, f32) {
+      let input = self.window.input();
+      (input.wheel_x, input.wheel_y)
    }
 
    pub fn set_background_color(&mut self, color: Color) {
       self.window.set_background_color(color);
    }
 
+   /// Counts the steps each key has been held for, a new press starts over.
+   fn update_held_keys(&mut self) {
+      let input = self.window.input();
+
+      for (held, (&down, &changed)) in self.key_held.iter_mut().zip(input.key_state.iter().zip(input.key_delta.iter())) {
+         *held = match (down, changed) {
+            (false, _) => 0,
+            (true, true) => 1,
+            (true, false) => held.saturating_add(1),
+         };
+      }
+   }
+
    pub fn draw_timing(&self, painter: &Painter, font: &Font, background_color: u8, foreground_color: u8) {
       let text = format!("FRAME: {:4.1} MS\nPAINT: {:4.1} MS\n STEP: {:4.1} MS\n BLIT: {:4.1} MS\nSLEEP: {:4.1} MS", self.frame_time, self.paint_time, self.step_time, self.blit_time, self.sleep_time);
       let text_rect = font.measure(&text);
       let background_rect = text_rect.tr(2, 2).grow(4, 4);
 
-      painter.clip(Some(background_rect));
+      painter.push_clip(background_rect);
       painter.rect_fill(background_rect, background_color);
       painter.text(background_rect.left + 2, background_rect.top + 2, &text, foreground_color, font);
-      painter.clip(None);
+      painter.pop_clip();
    }
 }
 
@@ -321,29 +685,163 @@ fn to_milisec(duration: Duration) -> f64 {
 }
 
 
-pub fn run<T: Application>(title: &str, width: u32, height: u32, scale: u32) -> Result<(), String> {
-   use std::sync::atomic::Ordering;
-   let was_alive = IS_TINY_CONTEXT_ALIVE.swap(true, Ordering::Relaxed);
-   if was_alive {
-      return Err("Cannot initialize Tiny more than once at a time".to_owned());
+/// Runs a single frame: pumps the window, runs as many fixed steps as fit in `elapsed`
+/// seconds, paints the application and finally blits the canvas.
+/// Returns `Ok(false)` when the application should quit.
+fn frame<T: Application>(context: &mut Context, app: &mut T, canvas: &mut Bitmap, elapsed: f64) -> Result<bool, String> {
+   let step_time;
+   let paint_time;
+   let blit_time;
+
+   // Handle messages, keeping the input from before for input recording and playback
+   let before = if context.recording_input() || context.playing_input() {
+      Some(context.window.input().clone())
+   } else {
+      None
+   };
+
+   let mut running = context.window.pump();
+   let mut elapsed = elapsed;
+
+   let played = match *context.input_playback.borrow_mut() {
+      Some(ref mut playback) => {
+         let input = context.window.input_mut();
+         *input = before.clone().unwrap();
+
+         if let Some((played_elapsed, closed)) = playback.frame(input) {
+            elapsed = played_elapsed;
+            running = running && !closed;
+         }
+
+         Some(playback.finished())
+      },
+      None => None,
+   };
+
+   if played == Some(true) {
+      context.log(LogLevel::Debug, "Input playback finished");
+      context.stop_input_playback();
    }
 
+   let failed = match (context.input_recorder.borrow_mut().as_mut(), before) {
+      (Some(recorder), Some(before)) => recorder.frame(elapsed, &before, context.window.input(), !running).err(),
+      _ => None,
+   };
+
+   if let Some(err) = failed {
+      context.log(LogLevel::Error, &err);
+      *context.input_recorder.borrow_mut() = None;
+   }
+
+   if !running {
+      return Ok(false);
+   }
+
+   {  // Step the application at a fixed rate, catching up on lost time
+      let step_now = Instant::now();
+      let step_length = context.timestep.step_length();
+
+      context.lag += elapsed;
 
-   let config = Config {
-      title: String::from(title),
-      width: width,
-      height: height,
-      scale: scale,
+      let mut steps = 0;
+      while context.lag >= step_length {
+         if steps == context.timestep.max_steps {
+            // Too far behind, drop the time we can't catch up on
+            context.log(LogLevel::Debug, &format!("Dropped {} steps", (context.lag / step_length) as u64));
+            context.lag %= step_length;
+            break;
+         }
+
+         context.update_held_keys();
+
+         if !app.step(context) {
+            return Ok(false);
+         }
+
+         context.window.input_mut().clear_deltas();
+         context.lag -= step_length;
+         context.steps += 1;
+         steps += 1;
+      }
+
+      step_time = to_milisec(step_now.elapsed());
+   }
+
+   {  // Let the application paint to the canvas
+      let paint_now = Instant::now();
+      let alpha = (context.lag / context.timestep.step_length()) as f32;
+
+      let p = BitmapPainter::new(canvas);
+      p.palette(Some(context.palette.borrow().clone()));
+      app.paint(context, &p, alpha.min(1.0));
+
+      paint_time = to_milisec(paint_now.elapsed());
+   }
+
+   // Save any screenshots requested during the frame
+   for path in context.screenshots.borrow_mut().drain(..) {
+      match canvas.save(&path, &context.palette.borrow()) {
+         Ok(_) => context.log(LogLevel::Debug, &format!("Saved screenshot {}", path.display())),
+         Err(err) => context.log(LogLevel::Error, &err),
+      }
+   }
+
+   {  // Blit canvas to the window
+      let blit_now = Instant::now();
+
+      context.window.paint(canvas, &context.palette.borrow().colors)?;
+
+      blit_time = to_milisec(blit_now.elapsed());
+   }
+
+   // Add the frame to the running recording, a failing recording is stopped
+   let failed = match *context.recorder.borrow_mut() {
+      Some(ref mut recorder) => recorder.frame(canvas, &context.palette.borrow().colors, elapsed).err(),
+      None => None,
    };
 
-   println!("Starting '{}' with resolution {}x{} at scale {}", config.title, config.width, config.height, config.scale);
+   if let Some(err) = failed {
+      context.log(LogLevel::Error, &err);
+      *context.recorder.borrow_mut() = None;
+   }
+
+   context.step_time = context.step_time * 0.9 + step_time * 0.1;
+   context.paint_time = context.paint_time * 0.9 + paint_time * 0.1;
+   context.blit_time = context.blit_time * 0.9 + blit_time * 0.1;
 
-   let mut context = match platform::Window::new(&config) {
-      Ok(window) => Context::new(window),
-      Err(err) => return Err(err),
+   Ok(true)
+}
+
+/// Picks the first `<prefix>-NNNN.<ext>` that doesn't exist yet, counting on from `count`.
+fn next_free_path(prefix: &str, ext: &str, count: &Cell<u32>) -> PathBuf {
+   let mut n = count.get();
+   let path = loop {
+      n += 1;
+      let path = PathBuf::from(format!("{}-{:04}.{}", prefix, n, ext));
+      if !path.exists() {
+         break path;
+      }
    };
 
-   let mut canvas = Bitmap::new(config.width, config.height);
+   count.set(n);
+   path
+}
+
+
+/// Runs the application until it quits or its window is closed.
+pub fn run<T: Application>(config: Config) -> Result<(), String> {
+   use std::sync::atomic::Ordering;
+   let was_alive = IS_TINY_CONTEXT_ALIVE.swap(true, Ordering::Relaxed);
+   if was_alive {
+      return Err("Cannot initialize Tiny more than once at a time".to_owned());
+   }
+
+   let config = T::configure(config);
+
+   config.log(LogLevel::Info, &format!("Starting '{}' with resolution {}x{} at scale {}", config.title(), config.width(), config.height(), config.scale()));
+
+   let mut canvas = Bitmap::new(config.width(), config.height());
+   let mut context = Context::new(config)?;
 
    // Initialize the application
    let mut app = match T::new(&mut context) {
@@ -353,69 +851,42 @@ pub fn run<T: Application>(title: &str, width: u32, height: u32, scale: u32) ->
    
    context.window.show();
 
-   let target_frame_time = 33_333_333u32; // An fps of 30Hz
-
-   let mut step_time;
-   let mut paint_time;
-   let mut blit_time;
    let mut frame_time;
    let mut sleep_time;
+   let mut last_frame = Instant::now();
 
    // Main loop
-   'main: loop {
+   loop {
       let frame_now = Instant::now();
+      let elapsed = to_milisec(frame_now.duration_since(last_frame)) / 1000.0;
+      last_frame = frame_now;
 
-      // Handle messages
-      if !context.window.pump() {
+      if !frame(&mut context, &mut app, &mut canvas, elapsed)? {
          break;
       }
 
-      {  // Step the application
-         let step_now = Instant::now();
-         
-         if !app.step(&context) {
-             break 'main;
-         }
-
-         step_time = to_milisec(step_now.elapsed());
-      }
+      let frame_duration = frame_now.elapsed();
+      frame_time = to_milisec(frame_duration);
 
-      {  // Let the application paint to the canvas
-         let paint_now = Instant::now();
+      context.frame_time = context.frame_time * 0.9 + frame_time * 0.1;
 
-         let p = BitmapPainter::new(&mut canvas);
-         app.paint(&context, &p);
+      // Sleep away the rest of the frame when the frame rate is capped
+      if let Some(frame_rate) = context.timestep.frame_rate {
+         let target_frame_time = Duration::new(0, 1_000_000_000 / cmp::max(frame_rate, 1));
 
-         paint_time = to_milisec(paint_now.elapsed());
-      }
+         if frame_duration < target_frame_time {
+            let sleep_duration = target_frame_time - frame_duration;
 
-      {  // Blit canvas to the window
-         let blit_now = Instant::now();
+            sleep_time = to_milisec(sleep_duration);
+            context.sleep_time = context.sleep_time * 0.98 + sleep_time * 0.02;
 
-         if let Err(err) = context.window.paint(&canvas, &context.palette.borrow_mut().colors) {
-            return Err(err);            
+            thread::sleep(sleep_duration);
          }
-
-         blit_time = to_milisec(blit_now.elapsed());
       }
+   }
 
-      let frame_duration = frame_now.elapsed();
-      frame_time = to_milisec(frame_duration);
-
-      context.step_time = context.step_time * 0.9 + step_time * 0.1;
-      context.paint_time = context.paint_time * 0.9 + paint_time * 0.1;
-      context.blit_time = context.blit_time * 0.9 + blit_time * 0.1;
-      context.frame_time = context.frame_time * 0.9 + frame_time * 0.1;
-
-      // Sleep to force the frame time to 33ms
-      if frame_duration.as_secs() == 0 && frame_duration.subsec_nanos() < target_frame_time {
-         let sleep_duration = Duration::new(0, target_frame_time - frame_duration.subsec_nanos());
-         
-         sleep_time = to_milisec(sleep_duration);
-         context.sleep_time = context.sleep_time * 0.98 + sleep_time * 0.02;
-         
-         thread::sleep(sleep_duration);
-      }
+   if let Err(err) = context.stop_recording().and_then(|_| context.stop_input_recording()) {
+      context.log(LogLevel::Error, &err);
    }
 
    let was_alive = IS_TINY_CONTEXT_ALIVE.swap(false, Ordering::Relaxed);
@@ -425,7 +896,335 @@ pub fn run<T: Application>(title: &str, width: u32, height: u32, scale: u32) ->
 
 #[cfg(test)]
 mod tests {
+   use super::*;
+
+   struct Cursor {
+      clicks: u32,
+      typed: String,
+      alpha: Cell<f32>,
+   }
+
+   impl Application for Cursor {
+      fn configure(config: Config) -> Config {
+         config.with_title("cursor")
+      }
+
+      fn new(_ctx: &mut Context) -> Result<Cursor, String> {
+         Ok(Cursor { clicks: 0, typed: String::new(), alpha: Cell::new(0.0) })
+      }
+
+      fn step(&mut self, ctx: &Context) -> bool {
+         if ctx.mouse_pressed(Mouse::Left) {
+            self.clicks += 1;
+         }
+
+         self.typed.extend(ctx.text_input().iter());
+         !ctx.key_pressed(Key::Escape)
+      }
+
+      fn paint(&self, ctx: &Context, painter: &Painter, alpha: f32) {
+         self.alpha.set(alpha);
+         let (x, y) = ctx.mouse_position();
+         painter.clear(BLACK);
+         painter.pixel(x as i32, y as i32, WHITE);
+      }
+   }
+
    #[test]
    fn it_works() {
    }
+
+   #[test]
+   fn headless_steps_and_paints() {
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+
+      app.mouse_move(3, 5);
+      assert!(app.step(2).unwrap());
+      assert_eq!(app.frames(), 2);
+      assert_eq!(app.canvas().pixel(3, 5), WHITE);
+      assert_eq!(app.canvas().pixel(0, 0), BLACK);
+      assert_eq!(app.context().mouse_position(), (3, 5));
+   }
+
+   #[test]
+   fn headless_scripted_input() {
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+
+      app.mouse_down(Mouse::Left);
+      app.text("gżΩ€\u{1F600}\n");
+      app.step(1).unwrap();
+      app.mouse_up(Mouse::Left);
+      app.step(1).unwrap();
+      assert_eq!(app.app().clicks, 1);
+      assert_eq!(app.app().typed, "gżΩ€\u{1F600}");
+
+      app.key_down(Key::Escape);
+      assert!(!app.step(5).unwrap());
+      assert_eq!(app.frames(), 2);
+   }
+
+   #[test]
+   fn headless_config() {
+      let config = Config::new("test", 12, 6)
+         .with_headless(false)
+         .with_palette(palette::dawn_bringer::create_palette())
+         .with_step_rate(15);
+
+      let app = Headless::<Cursor>::with_config(config).unwrap();
+      assert_eq!(app.context().config().title(), "cursor");
+      assert!(app.context().config().headless());
+      assert_eq!(app.context().timestep().step_rate, 15);
+      assert_eq!(app.palette().colors().len(), 33);
+      assert_eq!((app.canvas().width, app.canvas().height), (12, 6));
+   }
+
+   #[test]
+   fn fixed_timestep() {
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+      app.set_timestep(Timestep { step_rate: 10, max_steps: 4, frame_rate: None });
+
+      // Frames shorter than a step only paint
+      app.mouse_down(Mouse::Left);
+      app.advance(0.05).unwrap();
+      assert_eq!(app.context().steps(), 0);
+      assert!((app.app().alpha.get() - 0.5).abs() < 0.001);
+
+      // Input waits for the next step and is only seen once while catching up
+      app.advance(0.26).unwrap();
+      assert_eq!(app.context().steps(), 3);
+      assert_eq!(app.app().clicks, 1);
+      assert!((app.app().alpha.get() - 0.1).abs() < 0.001);
+
+      // Falling far behind is capped
+      app.advance(10.0).unwrap();
+      assert_eq!(app.context().steps(), 7);
+      assert!(app.app().alpha.get() < 1.0);
+
+      app.step(2).unwrap();
+      assert_eq!(app.context().steps(), 9);
+      assert_eq!(app.frames(), 5);
+   }
+
+   #[test]
+   fn headless_screenshot() {
+      let path = std::env::temp_dir().join(format!("tiny-screenshot-{}.png", std::process::id()));
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+
+      app.mouse_move(2, 1);
+      app.context().save_screenshot(&path);
+      app.step(1).unwrap();
+
+      let img = image::open(&path).unwrap().to_rgba();
+      assert_eq!(img.get_pixel(2, 1).data, [255, 255, 255, 255]);
+      assert_eq!(img.get_pixel(0, 0).data, [0, 0, 0, 255]);
+
+      std::fs::remove_file(&path).unwrap();
+   }
+
+   #[test]
+   fn headless_recording() {
+      let path = std::env::temp_dir().join(format!("tiny-capture-{}.gif", std::process::id()));
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+
+      app.context().start_recording(&path).unwrap();
+      for x in 0..4 {
+         app.mouse_move(x, 2);
+         app.step(1).unwrap();
+      }
+      assert!(app.context().recording());
+      app.context().stop_recording().unwrap();
+      assert!(!app.context().recording());
+
+      let mut decoder = gif::Decoder::new(std::fs::File::open(&path).unwrap()).read_info().unwrap();
+      let mut frames = 0;
+      while let Some(frame) = decoder.read_next_frame().unwrap() {
+         assert_eq!(frame.buffer[frames + 2 * 16], WHITE);
+         frames += 1;
+      }
+      assert_eq!(frames, 4);
+
+      std::fs::remove_file(&path).unwrap();
+   }
+
+   #[test]
+   fn released_and_modifiers() {
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+
+      app.key_down(Key::RShift);
+      app.key_down(Key::LControl);
+      app.key_down(Key::Key1);
+      app.mouse_down(Mouse::Right);
+      app.advance(0.0).unwrap();
+
+      let ctx = app.context();
+      assert_eq!(ctx.modifiers(), Modifiers { shift: true, ctrl: true, ..Modifiers::default() });
+      assert!(ctx.key_pressed(Key::Key1) && !ctx.key_released(Key::Key1));
+      assert!(!ctx.mouse_released(Mouse::Right));
+
+      app.step(1).unwrap();
+      app.key_up(Key::Key1);
+      app.key_up(Key::RShift);
+      app.mouse_up(Mouse::Right);
+      app.advance(0.0).unwrap();
+
+      let ctx = app.context();
+      assert!(ctx.key_released(Key::Key1) && !ctx.key_pressed(Key::Key1));
+      assert!(ctx.mouse_released(Mouse::Right));
+      assert_eq!(ctx.modifiers(), Modifiers { ctrl: true, ..Modifiers::default() });
+      assert!(!ctx.key_released(Key::LControl));
+   }
+
+   #[test]
+   fn key_repeat() {
+      let config = Config::new("test", 16, 8)
+         .with_step_rate(10)
+         .with_key_repeat(KeyRepeat { delay: 0.3, rate: 5.0 });
+
+      let mut app = Headless::<Cursor>::with_config(config).unwrap();
+      let mut repeats = Vec::new();
+
+      app.key_down(Key::Back);
+      for _ in 0..10 {
+         app.step(1).unwrap();
+         repeats.push(app.context().key_repeated(Key::Back));
+      }
+
+      // Pressed, then after 0.3s every 0.2s
+      assert_eq!(repeats, [true, false, false, true, false, true, false, true, false, true]);
+      assert!((app.context().key_held(Key::Back) - 0.9).abs() < 0.001);
+
+      // Releasing and pressing again starts over
+      app.key_up(Key::Back);
+      app.key_down(Key::Back);
+      app.step(1).unwrap();
+      assert!(app.context().key_repeated(Key::Back));
+      assert_eq!(app.context().key_held(Key::Back), 0.0);
+
+      app.key_up(Key::Back);
+      app.step(1).unwrap();
+      assert!(!app.context().key_repeated(Key::Back));
+      assert_eq!(app.context().key_held(Key::Back), 0.0);
+   }
+
+   #[test]
+   fn key_repeat_events() {
+      let mut app = Headless::<Cursor>::with_config(Config::new("test", 16, 8).with_step_rate(10)).unwrap();
+      let mut pressed = Vec::new();
+      let mut held = Vec::new();
+
+      // The OS sends key down again while a key is held, that is not a new press
+      for _ in 0..4 {
+         app.key_down(Key::Back);
+         app.advance(0.0).unwrap();
+         pressed.push(app.context().key_pressed(Key::Back));
+
+         app.step(1).unwrap();
+         held.push((app.context().key_held(Key::Back) * 10.0).round() as u32);
+      }
+
+      assert_eq!(pressed, [true, false, false, false]);
+      assert_eq!(held, [0, 1, 2, 3]);
+   }
+
+   #[test]
+   fn mouse_motion_and_wheel() {
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+
+      app.mouse_move(4, 4);
+      app.step(1).unwrap();
+      app.mouse_move(6, 3);
+      app.mouse_move(9, 2);
+      app.mouse_wheel(0.0, 1.0);
+      app.mouse_wheel(0.5, 2.0);
+      app.mouse_down(Mouse::X2);
+      app.advance(0.0).unwrap();
+
+      // Nothing is consumed until a step has run
+      assert_eq!(app.context().mouse_motion(), (5, -2));
+      assert_eq!(app.context().mouse_wheel(), (0.5, 3.0));
+      assert!(app.context().mouse_pressed(Mouse::X2));
+      assert!(!app.context().mouse_down(Mouse::X1));
+
+      app.step(1).unwrap();
+      assert_eq!(app.context().mouse_motion(), (0, 0));
+      assert_eq!(app.context().mouse_wheel(), (0.0, 0.0));
+      assert!(app.context().mouse_down(Mouse::X2));
+      assert!(!app.context().mouse_pressed(Mouse::X2));
+   }
+
+   #[test]
+   fn actions() {
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+      app.context_mut().set_actions(Actions::parse("select = mouse-left\nadd = shift+mouse-left\nset-group = ctrl+1").unwrap());
+
+      app.mouse_down(Mouse::Left);
+      app.advance(0.0).unwrap();
+      assert!(app.context().action_pressed("select") && app.context().action_down("select"));
+      assert!(!app.context().action_pressed("add") && !app.context().action_pressed("missing"));
+
+      app.step(1).unwrap();
+      app.mouse_up(Mouse::Left);
+      app.key_down(Key::LShift);
+      app.key_down(Key::Key1);
+      app.mouse_down(Mouse::Left);
+      app.advance(0.0).unwrap();
+      assert!(app.context().action_pressed("add") && !app.context().action_pressed("select"));
+      assert!(!app.context().action_down("set-group"));
+
+      app.step(1).unwrap();
+      app.key_up(Key::LShift);
+      app.mouse_up(Mouse::Left);
+      app.advance(0.0).unwrap();
+      assert!(app.context().action_released("select") && app.context().action_released("add"));
+   }
+
+   #[test]
+   fn input_replay() {
+      let path = std::env::temp_dir().join(format!("tiny-input-replay-{}.rec", std::process::id()));
+
+      let mut recorded = Headless::<Cursor>::new(16, 8).unwrap();
+      recorded.context().start_input_recording(&path).unwrap();
+
+      // Uneven frame times so some frames run no step and some several
+      recorded.mouse_move(5, 2);
+      recorded.mouse_down(Mouse::Left);
+      recorded.advance(0.04).unwrap();
+      recorded.text("hi");
+      recorded.mouse_up(Mouse::Left);
+      recorded.advance(0.1).unwrap();
+      recorded.mouse_down(Mouse::Left);
+      recorded.mouse_move(9, 6);
+      recorded.advance(0.02).unwrap();
+      recorded.step(3).unwrap();
+      recorded.context().stop_input_recording().unwrap();
+
+      let mut replayed = Headless::<Cursor>::new(16, 8).unwrap();
+      assert!(replayed.replay(&path).unwrap());
+
+      assert_eq!((replayed.app().clicks, &replayed.app().typed[..]), (2, "hi"));
+      assert_eq!((replayed.app().clicks, &replayed.app().typed), (recorded.app().clicks, &recorded.app().typed));
+      assert_eq!(replayed.app().alpha.get(), recorded.app().alpha.get());
+      assert_eq!(replayed.context().steps(), recorded.context().steps());
+      assert_eq!(replayed.frames(), recorded.frames());
+      assert!(*replayed.canvas().pixels.borrow() == *recorded.canvas().pixels.borrow());
+      assert!(!replayed.context().playing_input());
+
+      // Closing the application is part of the recording
+      recorded.context().start_input_recording(&path).unwrap();
+      recorded.key_down(Key::Escape);
+      assert!(!recorded.step(1).unwrap());
+      recorded.context().stop_input_recording().unwrap();
+
+      assert!(!replayed.replay(&path).unwrap());
+      std::fs::remove_file(&path).unwrap();
+   }
+
+   #[test]
+   fn headless_close() {
+      let mut app = Headless::<Cursor>::new(16, 8).unwrap();
+
+      app.close();
+      assert!(!app.step(1).unwrap());
+      assert!(!app.running());
+   }
 }
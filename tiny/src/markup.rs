@@ -0,0 +1,122 @@
+use std::ops::Range;
+
+/// A colour switch inside text, see `parse_markup`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorTag<'a> {
+   /// `{}`, back to the colour the text was drawn with.
+   Reset,
+   /// `{19}`, a palette index.
+   Index(u8),
+   /// `{cornflower}`, a palette colour name looked up ignoring case.
+   Name(&'a str),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Markup<'a> {
+   Char(char),
+   Color(ColorTag<'a>),
+}
+
+/// Splits text into characters and colour tags, along with the bytes each one takes up.
+///
+/// Tags are up to 32 letters, digits, spaces, `-` or `_` in braces. Anything else in braces
+/// is plain text, and `{{` is always a plain `{`.
+pub fn parse_markup<'a>(text: &'a str) -> MarkupIter<'a> {
+   MarkupIter {
+      text: text,
+      pos: 0,
+   }
+}
+
+/// Makes `text` show as it is when drawn, for example player names.
+pub fn escape_markup(text: &str) -> String {
+   text.replace('{', "{{")
+}
+
+/// The text with colour tags removed and escapes resolved.
+pub fn strip_markup(text: &str) -> String {
+   parse_markup(text).filter_map(|(_, markup)| match markup {
+      Markup::Char(ch) => Some(ch),
+      Markup::Color(_) => None,
+   }).collect()
+}
+
+pub struct MarkupIter<'a> {
+   text: &'a str,
+   pos: usize,
+}
+
+impl<'a> Iterator for MarkupIter<'a> {
+   type Item = (Range<usize>, Markup<'a>);
+
+   fn next(&mut self) -> Option<(Range<usize>, Markup<'a>)> {
+      let start = self.pos;
+      let rest = &self.text[start..];
+      let ch = rest.chars().next()?;
+
+      let (len, markup) = match ch {
+         '{' if rest[1..].starts_with('{') => (2, Markup::Char('{')),
+         '{' => match tag(&rest[1..]) {
+            Some((len, tag)) => (len + 2, Markup::Color(tag)),
+            None => (1, Markup::Char('{')),
+         },
+         _ => (ch.len_utf8(), Markup::Char(ch)),
+      };
+
+      self.pos += len;
+      Some((start..self.pos, markup))
+   }
+}
+
+/// Parses the tag after an opening brace, returning its length without the braces.
+fn tag<'a>(text: &'a str) -> Option<(usize, ColorTag<'a>)> {
+   let end = text.char_indices().take(33).find(|&(_, ch)| ch == '}').map(|(idx, _)| idx)?;
+   let name = &text[..end];
+
+   if !name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == ' ' || ch == '-' || ch == '_') {
+      return None;
+   }
+
+   let tag = if name.is_empty() {
+      ColorTag::Reset
+   } else if name.chars().all(|ch| ch.is_ascii_digit()) {
+      ColorTag::Index(name.parse::<u8>().ok()?)
+   } else {
+      ColorTag::Name(name)
+   };
+
+   Some((end, tag))
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn parse() {
+      let markup = parse_markup("a{Oiled cedar}b{{c{}{19}{x!}{300}").collect::<Vec<_>>();
+
+      assert_eq!(markup, [
+         (0..1, Markup::Char('a')),
+         (1..14, Markup::Color(ColorTag::Name("Oiled cedar"))),
+         (14..15, Markup::Char('b')),
+         (15..17, Markup::Char('{')),
+         (17..18, Markup::Char('c')),
+         (18..20, Markup::Color(ColorTag::Reset)),
+         (20..24, Markup::Color(ColorTag::Index(19))),
+         (24..25, Markup::Char('{')),
+         (25..26, Markup::Char('x')),
+         (26..27, Markup::Char('!')),
+         (27..28, Markup::Char('}')),
+         (28..29, Markup::Char('{')),
+         (29..30, Markup::Char('3')),
+         (30..31, Markup::Char('0')),
+         (31..32, Markup::Char('0')),
+         (32..33, Markup::Char('}')),
+      ]);
+
+      assert_eq!(strip_markup("{mandy}Error:{} {{x}"), "Error: {x}");
+      assert_eq!(strip_markup(&escape_markup("{plum}ß{")), "{plum}ß{");
+   }
+}
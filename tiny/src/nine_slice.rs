@@ -0,0 +1,137 @@
+use super::*;
+
+/// How the edges or centre of a `NineSlice` cover their part of the destination.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SliceFill {
+   Stretch,
+   /// Repeats the source, cutting off the last copy in each direction.
+   Tile,
+}
+
+/// A source region split into nine parts by four borders, for drawing frames of any size
+/// with `Painter::nine_slice`.
+///
+/// Corners are drawn as they are, edges are stretched or tiled along the frame and the
+/// centre fills what is left. Frames smaller than the borders cut the corners short.
+#[derive(Copy, Clone, PartialEq)]
+pub struct NineSlice {
+   pub rect: Rect,
+   pub left: i32,
+   pub top: i32,
+   pub right: i32,
+   pub bottom: i32,
+   pub edges: SliceFill,
+   /// `None` leaves the centre undrawn.
+   pub center: Option<SliceFill>,
+}
+
+impl NineSlice {
+   /// Splits `rect` with borders of the given widths, edges and centre are stretched.
+   pub fn new(rect: Rect, left: i32, top: i32, right: i32, bottom: i32) -> NineSlice {
+      NineSlice {
+         rect: rect,
+         left: left,
+         top: top,
+         right: right,
+         bottom: bottom,
+         edges: SliceFill::Stretch,
+         center: Some(SliceFill::Stretch),
+      }
+   }
+
+   /// Splits `rect` with the same border width on every side.
+   pub fn uniform(rect: Rect, border: i32) -> NineSlice {
+      NineSlice::new(rect, border, border, border, border)
+   }
+
+   pub fn with_edges(mut self, edges: SliceFill) -> NineSlice {
+      self.edges = edges;
+      self
+   }
+
+   pub fn with_center(mut self, center: Option<SliceFill>) -> NineSlice {
+      self.center = center;
+      self
+   }
+
+   /// Where each part goes when drawn over `dest_rect`, as destination and source rects along
+   /// with how the source covers the destination. Empty parts are left out.
+   pub fn parts(&self, dest_rect: Rect) -> Vec<(Rect, Rect, SliceFill)> {
+      let columns = spans(dest_rect.left, dest_rect.right, self.rect.left, self.rect.right, self.left, self.right);
+      let rows = spans(dest_rect.top, dest_rect.bottom, self.rect.top, self.rect.bottom, self.top, self.bottom);
+
+      let mut parts = Vec::new();
+      for (row, &(dest_top, dest_bottom, source_top, source_bottom)) in rows.iter().enumerate() {
+         for (column, &(dest_left, dest_right, source_left, source_right)) in columns.iter().enumerate() {
+            let fill = match (column == 1, row == 1) {
+               (true, true) => match self.center {
+                  Some(fill) => fill,
+                  None => continue,
+               },
+               (false, false) => SliceFill::Stretch,
+               _ => self.edges,
+            };
+
+            let dest = Rect::new(dest_left, dest_top, dest_right, dest_bottom);
+            let source = Rect::new(source_left, source_top, source_right, source_bottom);
+
+            if dest.width() > 0 && dest.height() > 0 && source.width() > 0 && source.height() > 0 {
+               parts.push((dest, source, fill));
+            }
+         }
+      }
+
+      parts
+   }
+}
+
+/// Splits a destination and source span into start border, middle and end border, as
+/// (dest start, dest end, source start, source end). Borders that don't fit are shrunk
+/// in proportion and keep their outer pixels.
+fn spans(dest_start: i32, dest_end: i32, source_start: i32, source_end: i32, start: i32, end: i32) -> [(i32, i32, i32, i32); 3] {
+   let size = cmp::max(dest_end - dest_start, 0);
+   let (start_fit, end_fit) = if start + end > size && start + end > 0 {
+      let start_fit = size * start / (start + end);
+      (start_fit, size - start_fit)
+   } else {
+      (start, end)
+   };
+
+   [
+      (dest_start, dest_start + start_fit, source_start, source_start + start_fit),
+      (dest_start + start_fit, dest_end - end_fit, source_start + start, source_end - end),
+      (dest_end - end_fit, dest_end, source_end - end_fit, source_end),
+   ]
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn rects(parts: &[(Rect, Rect, SliceFill)]) -> Vec<[i32; 8]> {
+      parts.iter().map(|&(d, s, _)| [d.left, d.top, d.width(), d.height(), s.left, s.top, s.width(), s.height()]).collect()
+   }
+
+   #[test]
+   fn parts() {
+      let slice = NineSlice::new(Rect::new_size(10, 20, 8, 6), 2, 1, 3, 2).with_edges(SliceFill::Tile);
+      let parts = slice.parts(Rect::new_size(0, 0, 20, 10));
+
+      assert_eq!(rects(&parts), [
+         [0, 0, 2, 1, 10, 20, 2, 1], [2, 0, 15, 1, 12, 20, 3, 1], [17, 0, 3, 1, 15, 20, 3, 1],
+         [0, 1, 2, 7, 10, 21, 2, 3], [2, 1, 15, 7, 12, 21, 3, 3], [17, 1, 3, 7, 15, 21, 3, 3],
+         [0, 8, 2, 2, 10, 24, 2, 2], [2, 8, 15, 2, 12, 24, 3, 2], [17, 8, 3, 2, 15, 24, 3, 2],
+      ]);
+
+      let fills = parts.iter().map(|part| part.2).collect::<Vec<_>>();
+      assert_eq!(fills[..5], [SliceFill::Stretch, SliceFill::Tile, SliceFill::Stretch, SliceFill::Tile, SliceFill::Stretch]);
+
+      // Too small for the borders, corners are cut and the middle is left out
+      let small = slice.with_center(None).parts(Rect::new_size(0, 0, 4, 3));
+      assert_eq!(rects(&small), [
+         [0, 0, 1, 1, 10, 20, 1, 1], [1, 0, 3, 1, 15, 20, 3, 1],
+         [0, 1, 1, 2, 10, 24, 1, 2], [1, 1, 3, 2, 15, 24, 3, 2],
+      ]);
+   }
+}
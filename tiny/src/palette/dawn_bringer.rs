@@ -72,6 +72,7 @@ pub fn create_palette() -> Palette {
          Color::new(143, 151, 74, 255),
          Color::new(138, 111, 48, 255),
       ],
+      names: names(),
    }
 }
 
@@ -0,0 +1,280 @@
+use super::super::*;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use image;
+
+// Palette files never contain the transparent colour at index zero. It is added when
+// loading and left out when saving, so indices match the built-in palettes.
+
+impl Palette {
+   /// Loads a palette, the format is picked from the file extension: `.gpl` (GIMP),
+   /// `.pal` (JASC), `.hex` or `.png` (one pixel per colour).
+   pub fn load(path: &Path) -> Result<Palette, String> {
+      match extension(path).as_str() {
+         "png" => {
+            let img = match image::open(path) {
+               Ok(img) => img.to_rgba(),
+               Err(err) => return Err(format!("Could not load palette {}: {}", path.display(), err)),
+            };
+
+            let colors = img.pixels()
+               .filter(|p| p.data[3] > 0)
+               .map(|p| (Color::new(p.data[0], p.data[1], p.data[2], p.data[3]), String::new()))
+               .collect();
+
+            Palette::from_entries(colors)
+         },
+         ext => {
+            let mut text = String::new();
+            if let Err(err) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+               return Err(format!("Could not load palette {}: {}", path.display(), err));
+            }
+
+            match ext {
+               "gpl" => Palette::from_gpl(&text),
+               "pal" => Palette::from_jasc(&text),
+               "hex" => Palette::from_hex(&text),
+               _ => Err(format!("Unknown palette format '{}'", path.display())),
+            }
+         },
+      }
+   }
+
+   /// Saves the palette, see `load` for the supported formats.
+   pub fn save(&self, path: &Path) -> Result<(), String> {
+      let text = match extension(path).as_str() {
+         "png" => {
+            let mut rgba = Vec::new();
+            for color in self.file_colors() {
+               rgba.extend_from_slice(&[color.red(), color.green(), color.blue(), color.alpha()]);
+            }
+
+            let len = rgba.len() as u32 / 4;
+            return match image::save_buffer(path, &rgba, len, 1, image::RGBA(8)) {
+               Ok(_) => Ok(()),
+               Err(err) => Err(format!("Could not save palette {}: {}", path.display(), err)),
+            };
+         },
+         "gpl" => {
+            let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            self.to_gpl(&name)
+         },
+         "pal" => self.to_jasc(),
+         "hex" => self.to_hex(),
+         _ => return Err(format!("Unknown palette format '{}'", path.display())),
+      };
+
+      match File::create(path).and_then(|mut f| f.write_all(text.as_bytes())) {
+         Ok(_) => Ok(()),
+         Err(err) => Err(format!("Could not save palette {}: {}", path.display(), err)),
+      }
+   }
+
+   pub fn from_gpl(text: &str) -> Result<Palette, String> {
+      let mut lines = text.lines();
+
+      if lines.next().map(|l| l.trim()) != Some("GIMP Palette") {
+         return Err(String::from("Missing 'GIMP Palette' header"));
+      }
+
+      let mut entries = Vec::new();
+      for line in lines {
+         let line = line.trim();
+         if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+            continue;
+         }
+
+         let mut parts = line.split_whitespace();
+         let mut channel = || parts.next().and_then(|p| p.parse::<u8>().ok());
+
+         let color = match (channel(), channel(), channel()) {
+            (Some(r), Some(g), Some(b)) => Color::new(r, g, b, 255),
+            _ => return Err(format!("Invalid colour '{}'", line)),
+         };
+
+         entries.push((color, parts.collect::<Vec<_>>().join(" ")));
+      }
+
+      Palette::from_entries(entries)
+   }
+
+   pub fn to_gpl(&self, name: &str) -> String {
+      let mut text = format!("GIMP Palette\nName: {}\nColumns: 8\n#\n", name);
+
+      for (idx, color) in self.file_colors().iter().enumerate() {
+         let name = &self.names[idx + self.file_offset()];
+         text.push_str(&format!("{:3} {:3} {:3}\t{}\n", color.red(), color.green(), color.blue(), name));
+      }
+
+      text
+   }
+
+   pub fn from_jasc(text: &str) -> Result<Palette, String> {
+      let mut lines = text.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+
+      if lines.next() != Some("JASC-PAL") {
+         return Err(String::from("Missing 'JASC-PAL' header"));
+      }
+
+      lines.next();
+      let count = match lines.next().and_then(|l| l.parse::<usize>().ok()) {
+         Some(count) => count,
+         None => return Err(String::from("Missing colour count")),
+      };
+
+      let mut entries = Vec::new();
+      for line in lines.take(count) {
+         let channels = line.split_whitespace().map(|p| p.parse::<u8>()).collect::<Vec<_>>();
+
+         match channels.as_slice() {
+            [Ok(r), Ok(g), Ok(b)] => entries.push((Color::new(*r, *g, *b, 255), String::new())),
+            _ => return Err(format!("Invalid colour '{}'", line)),
+         }
+      }
+
+      if entries.len() != count {
+         return Err(format!("Expected {} colours but found {}", count, entries.len()));
+      }
+
+      Palette::from_entries(entries)
+   }
+
+   pub fn to_jasc(&self) -> String {
+      let colors = self.file_colors();
+      let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", colors.len());
+
+      for color in colors {
+         text.push_str(&format!("{} {} {}\r\n", color.red(), color.green(), color.blue()));
+      }
+
+      text
+   }
+
+   /// Reads one `rrggbb` colour per line, as exported by Lospec.
+   pub fn from_hex(text: &str) -> Result<Palette, String> {
+      let mut entries = Vec::new();
+
+      for line in text.lines() {
+         let hex = line.trim().trim_start_matches('#');
+         if hex.is_empty() {
+            continue;
+         }
+
+         let value = match u32::from_str_radix(hex, 16) {
+            Ok(value) if hex.len() == 6 => value,
+            _ => return Err(format!("Invalid colour '{}'", line)),
+         };
+
+         entries.push((Color::new((value >> 16) as u8, (value >> 8) as u8, value as u8, 255), String::new()));
+      }
+
+      Palette::from_entries(entries)
+   }
+
+   pub fn to_hex(&self) -> String {
+      let mut text = String::new();
+
+      for color in self.file_colors() {
+         text.push_str(&format!("{:02x}{:02x}{:02x}\n", color.red(), color.green(), color.blue()));
+      }
+
+      text
+   }
+
+   fn from_entries(entries: Vec<(Color, String)>) -> Result<Palette, String> {
+      if entries.len() > 255 {
+         return Err(format!("Palette has {} colours, at most 255 are supported", entries.len()));
+      }
+
+      let mut palette = Palette {
+         colors: vec![Color::new(0, 0, 0, 0)],
+         names: vec![String::from("Transparent")],
+      };
+
+      for (color, name) in entries {
+         palette.colors.push(color);
+         palette.names.push(name);
+      }
+
+      Ok(palette)
+   }
+
+   #[inline]
+   fn file_offset(&self) -> usize {
+      match self.colors.first() {
+         Some(color) if color.alpha() == 0 => 1,
+         _ => 0,
+      }
+   }
+
+   fn file_colors(&self) -> &[Color] {
+      &self.colors[self.file_offset()..]
+   }
+}
+
+fn extension(path: &Path) -> String {
+   path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use palette::dawn_bringer as pal;
+
+   use std::env;
+   use std::fs;
+
+   #[test]
+   fn gpl_round_trip() {
+      let palette = pal::create_palette();
+      let text = palette.to_gpl("DB32");
+      assert!(text.starts_with("GIMP Palette\nName: DB32\n"));
+      assert!(text.contains(" 34  32  52\tValhalla\n"));
+
+      let loaded = Palette::from_gpl(&text).unwrap();
+      assert!(loaded.colors() == palette.colors());
+      assert_eq!(loaded.names(), palette.names());
+      assert_eq!(loaded.find("valhalla"), Some(pal::VALHALLA));
+      assert_eq!(loaded.name(pal::MANDY), Some("Mandy"));
+   }
+
+   #[test]
+   fn jasc_and_hex() {
+      let palette = pal::create_palette();
+
+      let jasc = Palette::from_jasc(&palette.to_jasc()).unwrap();
+      assert!(jasc.colors() == palette.colors());
+      assert_eq!(jasc.name(pal::MANDY), Some(""));
+
+      let hex = Palette::from_hex("#000000\n222034\n\nffffff\n").unwrap();
+      assert_eq!(hex.colors().len(), 4);
+      assert!(hex.colors()[2] == Color::new(34, 32, 52, 255));
+      assert!(palette.to_hex().starts_with("000000\n222034\n"));
+
+      assert!(Palette::from_hex("12345\n").is_err());
+      assert!(Palette::from_jasc("JASC-PAL\n0100\n2\n1 2 3\n").is_err());
+      assert!(Palette::from_gpl("0 0 0\n").is_err());
+   }
+
+   #[test]
+   fn files() {
+      let dir = env::temp_dir().join(format!("tiny-palette-{}", std::process::id()));
+      fs::create_dir_all(&dir).unwrap();
+
+      let palette = pal::create_palette();
+      for name in ["db32.gpl", "db32.pal", "db32.hex", "db32.png"].iter() {
+         let path = dir.join(name);
+         palette.save(&path).unwrap();
+         assert!(Palette::load(&path).unwrap().colors() == palette.colors(), "{}", name);
+      }
+
+      assert_eq!(Palette::load(&dir.join("db32.gpl")).unwrap().find("Stinge"), Some(pal::STINGE));
+      assert!(palette.save(&dir.join("db32.txt")).is_err());
+
+      fs::remove_dir_all(&dir).unwrap();
+   }
+}
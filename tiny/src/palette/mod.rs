@@ -1,3 +1,4 @@
-
 pub mod standard;
-pub mod dawn_bringer;
\ No newline at end of file
+pub mod dawn_bringer;
+
+mod file;
@@ -72,6 +72,7 @@ pub fn create_palette() -> Palette {
          Color { rgba: 0xff13e151 },
          Color { rgba: 0xffccfd08 },
       ],
+      names: names(),
    }
 }
 
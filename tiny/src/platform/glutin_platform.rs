@@ -8,9 +8,9 @@ extern crate glutin;
 use self::glutin::GlContext;
 use std::boxed::Box;
 use std::mem;
-use std::ptr;
 
 use super::super::*;
+use super::{Input, Viewport};
 
 pub const COLOR_OFFSET_R: u32 = 0;
 pub const COLOR_OFFSET_G: u32 = 8;
@@ -23,16 +23,7 @@ pub struct Window {
 
    background_color: Color,
   
-   pub key_state: [bool; 256],
-   pub key_delta: [bool; 256],
-
-   pub mouse_state: [bool; 3],
-   pub mouse_delta: [bool; 3],
-
-   pub text_input: Vec<char>,
-
-   pub mouse_x: u32,
-   pub mouse_y: u32,
+   pub input: Input,
 
    canvas_buffer: Vec<u32>,
    canvas_width: u32,
@@ -41,25 +32,27 @@ pub struct Window {
 
    window_width: u32,
    window_height: u32,
+   scaling: Scaling,
 }
 
 impl Window {
    pub fn new(config: &Config) -> Result<Window, String> {
-      let window_width = config.width * config.scale;
-      let window_height = config.height * config.scale;
+      let window_width = config.width() * config.scale();
+      let window_height = config.height() * config.scale();
 
       let mut canvas_buffer: Vec<u32> = Vec::new();
-      canvas_buffer.resize((config.width * config.height) as usize, 0 as u32);
+      canvas_buffer.resize((config.width() * config.height()) as usize, 0 as u32);
 
       let events_loop = Box::new(glutin::EventsLoop::new());
       let window = glutin::WindowBuilder::new()
          .with_dimensions(window_width, window_height)
          //.with_min_dimensions(window_width, window_height)
          //.with_max_dimensions(window_width, window_height)
-         .with_title(config.title.to_string())
+         .with_title(config.title().to_string())
          .with_visibility(true);
 
-      let context = glutin::ContextBuilder::new();
+      let context = glutin::ContextBuilder::new()
+         .with_vsync(config.vsync());
 
       let gl_window = match glutin::GlWindow::new(window, context, &events_loop) {
          Ok(win) => Box::new(win),
@@ -90,14 +83,17 @@ impl Window {
 
       // Restrict the size of the window
       if let Some((w, h)) = gl_window.get_outer_size() {
-         println!("Outer size {}x{}", w, h);
-         gl_window.set_min_dimensions(Some((w, h)));
-         gl_window.set_max_dimensions(Some((w, h)));
+         config.log(LogLevel::Debug, &format!("Outer size {}x{}", w, h));
+
+         if !config.resizable() {
+            gl_window.set_min_dimensions(Some((w, h)));
+            gl_window.set_max_dimensions(Some((w, h)));
+         }
       }
 
 
       if let Some((w, h)) = gl_window.get_inner_size() {
-         println!("Window size: {}x{} ({}x{})", w, h, window_width, window_height);
+         config.log(LogLevel::Debug, &format!("Window size: {}x{} ({}x{})", w, h, window_width, window_height));
       }
 
 
@@ -107,23 +103,15 @@ impl Window {
 
          background_color: Color::new(0, 0, 0, 255),
 
-         key_state: [false; 256],
-         key_delta: [false; 256],
-
-         text_input: Vec::with_capacity(8),
-
-         mouse_state: [false; 3],
-         mouse_delta: [false; 3],
-
-         mouse_x: 0,
-         mouse_y: 0,
+         input: Input::new(),
 
          canvas_buffer: canvas_buffer,
          canvas_tex: canvas_tex,
-         canvas_width: config.width,
-         canvas_height: config.height,
+         canvas_width: config.width(),
+         canvas_height: config.height(),
          window_width: window_width,
          window_height: window_height,
+         scaling: config.scaling(),
       })
    }
 
@@ -168,6 +156,13 @@ impl Window {
                         1.0);
          gl::Clear(gl::COLOR_BUFFER_BIT);
 
+         // The canvas is letterboxed, OpenGL has its origin in the bottom left
+         let viewport = Viewport::new(self.window_width, self.window_height, self.canvas_width, self.canvas_height, self.scaling);
+         let left = viewport.x as f32;
+         let right = (viewport.x + viewport.width as i32) as f32;
+         let bottom = (self.window_height as i32 - viewport.y - viewport.height as i32) as f32;
+         let top = bottom + viewport.height as f32;
+
          gl::Viewport(0, 0, self.window_width as i32, self.window_height as i32);
          gl::MatrixMode(gl::PROJECTION);
          gl::LoadIdentity();
@@ -180,16 +175,16 @@ impl Window {
 
          gl::Begin(gl::QUADS);
             gl::TexCoord2f(0.0, 0.0);
-            gl::Vertex2f(0.0, 0.0);
+            gl::Vertex2f(left, bottom);
 
             gl::TexCoord2f(1.0, 0.0);
-            gl::Vertex2f(self.window_width as f32, 0.0);
+            gl::Vertex2f(right, bottom);
 
             gl::TexCoord2f(1.0, 1.0);
-            gl::Vertex2f(self.window_width as f32, self.window_height as f32);
+            gl::Vertex2f(right, top);
 
             gl::TexCoord2f(0.0, 1.0);
-            gl::Vertex2f(0.0, self.window_height as f32);
+            gl::Vertex2f(left, top);
          gl::End();
       }
 
@@ -204,25 +199,14 @@ impl Window {
       let mut running = true;
       //let window = &self.window;
 
-      unsafe { ptr::write_bytes::<bool>(self.key_delta.as_mut_ptr(), 0, 256); }
-      unsafe { ptr::write_bytes::<bool>(self.mouse_delta.as_mut_ptr(), 0, 3); }
-
       let events_loop = &mut self.events_loop;
       let window = &mut self.window;
-      let key_state = &mut self.key_state;
-      let key_delta = &mut self.key_delta;
-      let mouse_state = &mut self.mouse_state;
-      let mouse_delta = &mut self.mouse_delta;
+      let input = &mut self.input;
       let window_width = &mut self.window_width;
       let window_height = &mut self.window_height;
       let canvas_width = self.canvas_width;
       let canvas_height = self.canvas_height;
-      let mouse_x = &mut self.mouse_x;
-      let mouse_y = &mut self.mouse_y;
-      let text_input = &mut self.text_input;
-
-      // Clear any previous text
-      text_input.clear();
+      let scaling = self.scaling;
 
       events_loop.poll_events(|event| {
          match event {
@@ -236,25 +220,16 @@ impl Window {
                },
 
                glutin::WindowEvent::ReceivedCharacter(ch) => {
-                  if ch.is_ascii() && !ch.is_control() {
-                     text_input.push(ch);
+                  if !ch.is_control() {
+                     input.text_input.push(ch);
                   }
                },
 
-               glutin::WindowEvent::KeyboardInput { input, .. } => {
-                  if let Some(virtual_keycode) = input.virtual_keycode {
+               glutin::WindowEvent::KeyboardInput { input: event, .. } => {
+                  if let Some(virtual_keycode) = event.virtual_keycode {
                      let key = keycode_glutin_to_tiny(virtual_keycode);
 
-                     match input.state {
-                        glutin::ElementState::Pressed => {
-                           key_state[key as usize] = true;
-                           key_delta[key as usize] = true;
-                        },
-                        glutin::ElementState::Released => {
-                           key_state[key as usize] = false;
-                           key_delta[key as usize] = true;
-                        },              
-                     }
+                     input.set_key(key, event.state == glutin::ElementState::Pressed);
                   }
                },
 
@@ -263,26 +238,26 @@ impl Window {
                      glutin::MouseButton::Left => Some(Mouse::Left),
                      glutin::MouseButton::Right => Some(Mouse::Right),
                      glutin::MouseButton::Middle => Some(Mouse::Middle),
+                     // X11 reports the back and forward buttons as 8 and 9
+                     glutin::MouseButton::Other(8) => Some(Mouse::X1),
+                     glutin::MouseButton::Other(9) => Some(Mouse::X2),
                      _ => None,
                   };
 
                   if let Some(button) = button {
-                     match state {
-                        glutin::ElementState::Pressed => {
-                           mouse_state[button as usize] = true;
-                           mouse_delta[button as usize] = true;
-                        },
-                        glutin::ElementState::Released => {
-                           mouse_state[button as usize] = false;
-                           mouse_delta[button as usize] = true;
-                        },              
-                     }
+                     input.set_mouse(button, state == glutin::ElementState::Pressed);
                   }
                },
 
                glutin::WindowEvent::CursorMoved { position, .. } => {
-                  *mouse_x = ((position.0 / *window_width as f64) * canvas_width as f64) as u32;
-                  *mouse_y = ((position.1 / *window_height as f64) * canvas_height as f64) as u32;
+                  let viewport = Viewport::new(*window_width, *window_height, canvas_width, canvas_height, scaling);
+                  input.move_mouse(&viewport, position.0, position.1);
+               },
+
+               glutin::WindowEvent::MouseWheel { delta, .. } => match delta {
+                  glutin::MouseScrollDelta::LineDelta(x, y) => input.scroll(x, y),
+                  // Touchpads scroll in pixels, treat a line as roughly 16 of them
+                  glutin::MouseScrollDelta::PixelDelta(x, y) => input.scroll(x / 16.0, y / 16.0),
                },
 
                _ => (),
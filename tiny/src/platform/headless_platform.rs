@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+use super::super::*;
+use super::{Input, Viewport};
+
+/// Scripted input, applied to the input state on the next call to `pump`.
+#[derive(Copy, Clone)]
+pub enum Event {
+   KeyDown(Key),
+   KeyUp(Key),
+   MouseMove(u32, u32),
+   MouseDown(Mouse),
+   MouseUp(Mouse),
+   MouseWheel(f32, f32),
+   Text(char),
+   Close,
+}
+
+/// A window that never touches the display, the canvas is only kept in memory.
+pub struct Window {
+   pub events: VecDeque<Event>,
+   pub input: Input,
+   pub frames: u64,
+
+   background_color: Color,
+
+   canvas_width: u32,
+   canvas_height: u32,
+}
+
+impl Window {
+   pub fn new(config: &Config) -> Window {
+      Window {
+         events: VecDeque::new(),
+         input: Input::new(),
+         frames: 0,
+
+         background_color: Color::new(0, 0, 0, 255),
+
+         canvas_width: config.width(),
+         canvas_height: config.height(),
+      }
+   }
+
+   #[allow(dead_code)]
+   pub fn background_color(&self) -> Color {
+      self.background_color
+   }
+
+   pub fn set_background_color(&mut self, color: Color) {
+      self.background_color = color;
+   }
+
+   pub fn paint(&mut self, bitmap: &Bitmap, _palette_colors: &Vec<Color>) -> Result<(), String> {
+      if bitmap.width != self.canvas_width || bitmap.height != self.canvas_height {
+         return Err(format!("Canvas is {}x{} but the window expects {}x{}", bitmap.width, bitmap.height, self.canvas_width, self.canvas_height));
+      }
+
+      self.frames += 1;
+      Ok(())
+   }
+
+   pub fn pump(&mut self) -> bool {
+      let mut running = true;
+
+      while let Some(event) = self.events.pop_front() {
+         match event {
+            Event::KeyDown(key) => self.input.set_key(key, true),
+            Event::KeyUp(key) => self.input.set_key(key, false),
+            Event::MouseMove(x, y) => {
+               // Scripted positions are in canvas pixels, the window is the canvas
+               let viewport = Viewport::new(self.canvas_width, self.canvas_height, self.canvas_width, self.canvas_height, Scaling::Fit);
+               self.input.move_mouse(&viewport, x as f64, y as f64);
+            },
+            Event::MouseDown(button) => self.input.set_mouse(button, true),
+            Event::MouseUp(button) => self.input.set_mouse(button, false),
+            Event::MouseWheel(x, y) => self.input.scroll(x, y),
+            Event::Text(ch) => {
+               if !ch.is_control() {
+                  self.input.text_input.push(ch);
+               }
+            },
+            Event::Close => running = false,
+         }
+      }
+
+      running
+   }
+}
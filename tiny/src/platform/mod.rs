@@ -1,9 +1,293 @@
-pub use self::platform::*;
+pub use self::native::{COLOR_OFFSET_R, COLOR_OFFSET_G, COLOR_OFFSET_B, COLOR_OFFSET_A};
+
+use super::*;
+
+use std::ptr;
 
 #[cfg(target_os = "windows")]
 #[path="win32_platform.rs"]
-mod platform;
+mod native;
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 #[path="glutin_platform.rs"]
-mod platform;
+mod native;
+
+#[path="headless_platform.rs"]
+pub mod headless;
+
+
+/// Input state as seen by the application, updated by the backend in `pump`.
+///
+/// Deltas and text are kept until a step has seen them, so none are lost on frames
+/// without a step and none are repeated when several steps run in one frame.
+#[derive(Clone)]
+pub struct Input {
+   pub key_state: [bool; 256],
+   pub key_delta: [bool; 256],
+
+   pub mouse_state: [bool; 5],
+   pub mouse_delta: [bool; 5],
+
+   pub text_input: Vec<char>,
+
+   pub mouse_x: u32,
+   pub mouse_y: u32,
+   pub mouse_dx: i32,
+   pub mouse_dy: i32,
+
+   pub wheel_x: f32,
+   pub wheel_y: f32,
+
+   /// Unclamped position in canvas units and the part of the motion that is less than a
+   /// whole canvas pixel, carried over to the next move.
+   mouse_exact: (f64, f64),
+   mouse_rest: (f64, f64),
+}
+
+impl Input {
+   pub fn new() -> Input {
+      Input {
+         key_state: [false; 256],
+         key_delta: [false; 256],
+
+         mouse_state: [false; 5],
+         mouse_delta: [false; 5],
+
+         text_input: Vec::with_capacity(8),
+
+         mouse_x: 0,
+         mouse_y: 0,
+         mouse_dx: 0,
+         mouse_dy: 0,
+
+         wheel_x: 0.0,
+         wheel_y: 0.0,
+
+         mouse_exact: (0.0, 0.0),
+         mouse_rest: (0.0, 0.0),
+      }
+   }
+
+   /// Clears everything that only lives for a single step.
+   pub fn clear_deltas(&mut self) {
+      unsafe { ptr::write_bytes::<bool>(self.key_delta.as_mut_ptr(), 0, 256); }
+      unsafe { ptr::write_bytes::<bool>(self.mouse_delta.as_mut_ptr(), 0, 5); }
+
+      self.mouse_dx = 0;
+      self.mouse_dy = 0;
+      self.wheel_x = 0.0;
+      self.wheel_y = 0.0;
+
+      self.text_input.clear();
+   }
+
+   /// Sets the state of a key, auto-repeat events for a key that is already down are ignored.
+   pub fn set_key(&mut self, key: Key, down: bool) {
+      if self.key_state[key as usize] != down {
+         self.key_state[key as usize] = down;
+         self.key_delta[key as usize] = true;
+      }
+   }
+
+   pub fn set_mouse(&mut self, button: Mouse, down: bool) {
+      self.mouse_state[button as usize] = down;
+      self.mouse_delta[button as usize] = true;
+   }
+
+   /// Moves the mouse to a position in the window, adding to the motion since the last step.
+   ///
+   /// The motion is measured before the position is clamped to the canvas, so it keeps going
+   /// past the canvas edge, and moves smaller than a canvas pixel add up.
+   pub fn move_mouse(&mut self, viewport: &Viewport, x: f64, y: f64) {
+      let (exact_x, exact_y) = viewport.to_canvas_exact(x, y);
+
+      if exact_x.is_finite() && exact_y.is_finite() {
+         let dx = exact_x - self.mouse_exact.0 + self.mouse_rest.0;
+         let dy = exact_y - self.mouse_exact.1 + self.mouse_rest.1;
+
+         self.mouse_dx += dx.trunc() as i32;
+         self.mouse_dy += dy.trunc() as i32;
+         self.mouse_rest = (dx.fract(), dy.fract());
+         self.mouse_exact = (exact_x, exact_y);
+      }
+
+      let (x, y) = viewport.to_canvas(x, y);
+      self.mouse_x = x;
+      self.mouse_y = y;
+   }
+
+   /// Adds wheel movement in lines, positive is away from the user or to the right.
+   pub fn scroll(&mut self, x: f32, y: f32) {
+      self.wheel_x += x;
+      self.wheel_y += y;
+   }
+}
+
+
+/// Where the canvas is shown inside the window, in window pixels.
+///
+/// With `Scaling::Integer` the canvas is scaled by the largest whole number that fits,
+/// falling back to fitting when the window is smaller than the canvas. Either way it is
+/// centred and the rest of the window shows the background colour.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+   pub x: i32,
+   pub y: i32,
+   pub width: u32,
+   pub height: u32,
+
+   canvas_width: u32,
+   canvas_height: u32,
+}
+
+impl Viewport {
+   pub fn new(window_width: u32, window_height: u32, canvas_width: u32, canvas_height: u32, scaling: Scaling) -> Viewport {
+      let fit = f64::min(window_width as f64 / canvas_width as f64, window_height as f64 / canvas_height as f64);
+
+      let scale = match scaling {
+         Scaling::Integer if fit >= 1.0 => fit.floor(),
+         _ => fit,
+      };
+
+      let width = (canvas_width as f64 * scale).round() as u32;
+      let height = (canvas_height as f64 * scale).round() as u32;
+
+      Viewport {
+         x: (window_width as i32 - width as i32) / 2,
+         y: (window_height as i32 - height as i32) / 2,
+         width: width,
+         height: height,
+         canvas_width: canvas_width,
+         canvas_height: canvas_height,
+      }
+   }
+
+   /// Maps a position in the window to the canvas pixel under it, positions outside
+   /// the canvas are clamped to its edges.
+   pub fn to_canvas(&self, x: f64, y: f64) -> (u32, u32) {
+      let (x, y) = self.to_canvas_exact(x, y);
+      let clamp = |pos: f64, canvas_size: u32| pos.floor().max(0.0).min(canvas_size as f64 - 1.0) as u32;
+
+      (clamp(x, self.canvas_width), clamp(y, self.canvas_height))
+   }
+
+   /// Maps a position in the window to canvas units without rounding or clamping it.
+   pub fn to_canvas_exact(&self, x: f64, y: f64) -> (f64, f64) {
+      let map = |pos: f64, offset: i32, size: u32, canvas_size: u32| (pos - offset as f64) / size as f64 * canvas_size as f64;
+
+      (map(x, self.x, self.width, self.canvas_width), map(y, self.y, self.height, self.canvas_height))
+   }
+}
+
+
+pub enum Window {
+   Native(native::Window),
+   Headless(headless::Window),
+}
+
+impl Window {
+   pub fn new(config: &Config) -> Result<Window, String> {
+      if config.headless() {
+         Ok(Window::Headless(headless::Window::new(config)))
+      } else {
+         native::Window::new(config).map(Window::Native)
+      }
+   }
+
+   pub fn input(&self) -> &Input {
+      match *self {
+         Window::Native(ref window) => &window.input,
+         Window::Headless(ref window) => &window.input,
+      }
+   }
+
+   pub fn input_mut(&mut self) -> &mut Input {
+      match *self {
+         Window::Native(ref mut window) => &mut window.input,
+         Window::Headless(ref mut window) => &mut window.input,
+      }
+   }
+
+   pub fn headless_mut(&mut self) -> Option<&mut headless::Window> {
+      match *self {
+         Window::Headless(ref mut window) => Some(window),
+         _ => None,
+      }
+   }
+
+   pub fn show(&self) {
+      match *self {
+         Window::Native(ref window) => window.show(),
+         Window::Headless(_) => (),
+      }
+   }
+
+   pub fn set_background_color(&mut self, color: Color) {
+      match *self {
+         Window::Native(ref mut window) => window.set_background_color(color),
+         Window::Headless(ref mut window) => window.set_background_color(color),
+      }
+   }
+
+   pub fn paint(&mut self, bitmap: &Bitmap, palette_colors: &Vec<Color>) -> Result<(), String> {
+      match *self {
+         Window::Native(ref mut window) => window.paint(bitmap, palette_colors),
+         Window::Headless(ref mut window) => window.paint(bitmap, palette_colors),
+      }
+   }
+
+   pub fn pump(&mut self) -> bool {
+      match *self {
+         Window::Native(ref mut window) => window.pump(),
+         Window::Headless(ref mut window) => window.pump(),
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn viewport() {
+      let exact = Viewport::new(960, 600, 320, 200, Scaling::Integer);
+      assert_eq!((exact.x, exact.y, exact.width, exact.height), (0, 0, 960, 600));
+
+      // Letterboxed at the largest whole scale
+      let boxed = Viewport::new(1000, 700, 320, 200, Scaling::Integer);
+      assert_eq!((boxed.x, boxed.y, boxed.width, boxed.height), (20, 50, 960, 600));
+      assert_eq!(boxed.to_canvas(20.0, 50.0), (0, 0));
+      assert_eq!(boxed.to_canvas(22.9, 53.0), (0, 1));
+      assert_eq!(boxed.to_canvas(500.0, 350.0), (160, 100));
+      assert_eq!(boxed.to_canvas(5.0, 690.0), (0, 199));
+
+      let fit = Viewport::new(1000, 700, 320, 200, Scaling::Fit);
+      assert_eq!((fit.x, fit.y, fit.width, fit.height), (0, 37, 1000, 625));
+
+      let small = Viewport::new(160, 200, 320, 200, Scaling::Integer);
+      assert_eq!((small.x, small.y, small.width, small.height), (0, 50, 160, 100));
+   }
+
+   #[test]
+   fn mouse_motion() {
+      let viewport = Viewport::new(1000, 700, 320, 200, Scaling::Integer);
+      let mut input = Input::new();
+
+      input.move_mouse(&viewport, 20.0, 50.0);
+      input.clear_deltas();
+
+      // Moves of a single window pixel add up to whole canvas pixels
+      for x in 21..29 {
+         input.move_mouse(&viewport, x as f64, 50.0);
+      }
+      assert_eq!((input.mouse_x, input.mouse_dx), (2, 2));
+
+      // Past the edge of the canvas the position stops but the motion doesn't
+      input.clear_deltas();
+      input.move_mouse(&viewport, 3.0, 50.0);
+      input.move_mouse(&viewport, 0.0, 20.0);
+      assert_eq!((input.mouse_x, input.mouse_y), (0, 0));
+      assert_eq!((input.mouse_dx, input.mouse_dy), (-8, -10));
+   }
+}
@@ -7,7 +7,7 @@ use std::mem;
 use std::ffi::{OsStr};
 use std::os::windows::ffi::OsStrExt;
 
-use winapi::shared::windef::{HWND, RECT, POINT, HBRUSH};
+use winapi::shared::windef::{HWND, RECT, POINT, HBRUSH, HGDIOBJ};
 use winapi::shared::minwindef::{FALSE, WPARAM, LPARAM, LRESULT, UINT, DWORD, HINSTANCE};
 use winapi::um::winnt::{LONG, LPCWSTR, VOID};
 use winapi::um::wingdi;
@@ -16,6 +16,7 @@ use winapi::um::libloaderapi;
 use winapi::shared::windowsx;
 
 use super::super::*;
+use super::{Input, Viewport};
 
 #[derive(Copy, Clone)]
 enum Event {
@@ -24,11 +25,15 @@ enum Event {
     MouseMove(i32, i32),
     MouseDown(Mouse),
     MouseUp(Mouse),
+    MouseWheel(f32, f32),
     Text(char),
 }
 
 static mut WIN_EVENT: Option<Event> = None;
 
+// First half of a character outside the basic multilingual plane, WM_CHAR sends UTF-16
+static mut HIGH_SURROGATE: u16 = 0;
+
 pub const COLOR_OFFSET_R: u32 = 16;
 pub const COLOR_OFFSET_G: u32 = 8;
 pub const COLOR_OFFSET_B: u32 = 0;
@@ -42,21 +47,13 @@ pub struct Window {
 
    background_color: Color,
    
-   pub key_state: [bool; 256],
-   pub key_delta: [bool; 256],
-
-   pub mouse_state: [bool; 3],
-   pub mouse_delta: [bool; 3],
-
-   pub text_input: Vec<char>,
-
-   pub mouse_x: u32,
-   pub mouse_y: u32,
+   pub input: Input,
 
    canvas_width: u32,
    canvas_height: u32,
    window_width: u32,
    window_height: u32,
+   scaling: Scaling,
 }
 
 fn to_wstring(str: &str) -> Vec<u16> {
@@ -75,8 +72,8 @@ impl Window {
          let screen_width = winuser::GetSystemMetrics(winuser::SM_CXSCREEN) as u32;
          let screen_height = winuser::GetSystemMetrics(winuser::SM_CYSCREEN) as u32;
 
-         let window_width = config.width * config.scale;
-         let window_height = config.height * config.scale;
+         let window_width = config.width() * config.scale();
+         let window_height = config.height() * config.scale();
          let window_left = (screen_width - window_width) / 2;
          let window_top = (screen_height - window_height) / 2;
 
@@ -85,12 +82,19 @@ impl Window {
             top: window_top as LONG, bottom: (window_top + window_height) as LONG,
          };
 
-         let style = winuser::WS_CAPTION | winuser::WS_SYSMENU | winuser::WS_MINIMIZEBOX;
+         let mut style = winuser::WS_CAPTION | winuser::WS_SYSMENU | winuser::WS_MINIMIZEBOX;
+         if config.resizable() {
+            style |= winuser::WS_THICKFRAME | winuser::WS_MAXIMIZEBOX;
+         }
          winuser::AdjustWindowRect(&mut rc, style, FALSE);
 
+         if config.vsync() {
+            config.log(LogLevel::Debug, "Vsync is not supported by the win32 backend");
+         }
+
          let handle = winuser::CreateWindowExW(0,
                                                class_name.as_ptr(),
-                                               to_wstring(config.title.as_str()).as_ptr() as LPCWSTR,
+                                               to_wstring(config.title()).as_ptr() as LPCWSTR,
                                                style,
                                                rc.left, rc.top,
                                                rc.right - rc.left, rc.bottom - rc.top,
@@ -102,8 +106,8 @@ impl Window {
          let window_bmi = wingdi::BITMAPINFO {
             bmiHeader: wingdi::BITMAPINFOHEADER {
                biSize: mem::size_of::<wingdi::BITMAPINFOHEADER>() as DWORD,
-               biWidth: config.width as LONG,
-               biHeight: -(config.height as LONG),
+               biWidth: config.width() as LONG,
+               biHeight: -(config.height() as LONG),
                biPlanes: 1,
                biBitCount: 32,
                biCompression: wingdi::BI_RGB,
@@ -126,21 +130,13 @@ impl Window {
 
             background_color: Color::new(0, 0, 0, 255),
 
-            key_state: [false; 256],
-            key_delta: [false; 256],
-
-            text_input: Vec::with_capacity(8),
-
-            mouse_state: [false; 3],
-            mouse_delta: [false; 3],
+            input: Input::new(),
 
-            mouse_x: 0,
-            mouse_y: 0,
-
-            canvas_width: config.width,
-            canvas_height: config.height,
+            canvas_width: config.width(),
+            canvas_height: config.height(),
             window_width: window_width,
             window_height: window_height,
+            scaling: config.scaling(),
          })
       }
    }
@@ -175,10 +171,33 @@ impl Window {
             *canvas_pixels.offset(i) = palette_colors[*bitmap_pixels.offset(i) as usize].rgba;
          }
 
+         self.update_size();
+         let viewport = Viewport::new(self.window_width, self.window_height, self.canvas_width, self.canvas_height, self.scaling);
+
          let dc = winuser::GetDC(self.handle);
 
+         // Fill the borders around the letterboxed canvas
+         let color = self.background_color;
+         let brush = wingdi::CreateSolidBrush(wingdi::RGB(color.red(), color.green(), color.blue()));
+         let right = viewport.x + viewport.width as i32;
+         let bottom = viewport.y + viewport.height as i32;
+         let borders = [
+            RECT { left: 0, top: 0, right: self.window_width as LONG, bottom: viewport.y },
+            RECT { left: 0, top: bottom, right: self.window_width as LONG, bottom: self.window_height as LONG },
+            RECT { left: 0, top: viewport.y, right: viewport.x, bottom: bottom },
+            RECT { left: right, top: viewport.y, right: self.window_width as LONG, bottom: bottom },
+         ];
+
+         for rect in borders.iter() {
+            if rect.right > rect.left && rect.bottom > rect.top {
+               winuser::FillRect(dc, rect, brush);
+            }
+         }
+
+         wingdi::DeleteObject(brush as HGDIOBJ);
+
          wingdi::StretchDIBits(dc,
-                               0, 0, self.window_width as i32, self.window_height as i32,
+                               viewport.x, viewport.y, viewport.width as i32, viewport.height as i32,
                                0, 0, self.canvas_width as i32, self.canvas_height as i32,
                                mem::transmute::<*mut u32, *const VOID>(canvas_pixels),
                                &self.window_bmi,
@@ -191,6 +210,16 @@ impl Window {
       Ok(())
    }
 
+   /// Picks up the current client area size, it changes when the window is resized.
+   unsafe fn update_size(&mut self) {
+      let mut rc = RECT { left: 0, top: 0, right: 0, bottom: 0 };
+
+      if winuser::GetClientRect(self.handle, &mut rc) != FALSE && rc.right > 0 && rc.bottom > 0 {
+         self.window_width = rc.right as u32;
+         self.window_height = rc.bottom as u32;
+      }
+   }
+
    pub fn pump(&mut self) -> bool {
       unsafe {
          let mut msg = winuser::MSG {
@@ -202,11 +231,6 @@ impl Window {
             pt: POINT { x: 0, y: 0 },
          };
 
-         ptr::write_bytes::<bool>(self.key_delta.as_mut_ptr(), 0, 256);
-         ptr::write_bytes::<bool>(self.mouse_delta.as_mut_ptr(), 0, 3);
-
-         self.text_input.clear();
-
          while winuser::PeekMessageW(&mut msg, 0 as HWND, 0, 0, winuser::PM_REMOVE) != FALSE {
 
             if msg.message == winuser::WM_QUIT {
@@ -220,37 +244,38 @@ impl Window {
                match event {
                   Event::KeyDown(key) => {
                      if let Some(key) = keycode_win32_to_tiny(key) {
-                        self.key_state[key as usize] = true;
-                        self.key_delta[key as usize] = true;
+                        self.input.set_key(key, true);
                      }
                   },
                     
                   Event::KeyUp(key) => {
                      if let Some(key) = keycode_win32_to_tiny(key) {
-                        self.key_state[key as usize] = false;
-                        self.key_delta[key as usize] = true;
+                        self.input.set_key(key, false);
                      }
                   },
 
                   Event::Text(ch) => {
-                     if ch.is_ascii() && !ch.is_control() {
-                        self.text_input.push(ch);
+                     if !ch.is_control() {
+                        self.input.text_input.push(ch);
                      }
                   },
 
                   Event::MouseMove(x, y) => {
-                     self.mouse_x = ((x as f64 / self.window_width as f64) * self.canvas_width as f64) as u32;
-                     self.mouse_y = ((y as f64 / self.window_height as f64) * self.canvas_height as f64) as u32;
+                     self.update_size();
+                     let viewport = Viewport::new(self.window_width, self.window_height, self.canvas_width, self.canvas_height, self.scaling);
+                     self.input.move_mouse(&viewport, x as f64, y as f64);
+                  },
+
+                  Event::MouseWheel(x, y) => {
+                     self.input.scroll(x, y);
                   },
 
                   Event::MouseDown(button) => {
-                     self.mouse_state[button as usize] = true;
-                     self.mouse_delta[button as usize] = true;
+                     self.input.set_mouse(button, true);
                   },
 
                   Event::MouseUp(button) => {
-                     self.mouse_state[button as usize] = false;
-                     self.mouse_delta[button as usize] = true;
+                     self.input.set_mouse(button, false);
                   },
                }
             }
@@ -297,17 +322,28 @@ unsafe extern "system" fn wnd_callback(window: HWND, msg: UINT, wparam: WPARAM,
          winuser::PostQuitMessage(0);
       },
 
-      winuser::WM_KEYDOWN => {
+      // Alt and keys pressed while holding it arrive as system keys
+      winuser::WM_KEYDOWN | winuser::WM_SYSKEYDOWN => {
          //println!("key down: {}", wparam);
          WIN_EVENT = Some(Event::KeyDown(wparam as u8));
       },
 
-      winuser::WM_KEYUP => {
+      winuser::WM_KEYUP | winuser::WM_SYSKEYUP => {
          WIN_EVENT = Some(Event::KeyUp(wparam as u8));
       },
 
       winuser::WM_CHAR => {
-         WIN_EVENT = Some(Event::Text(wparam as u8 as char));
+         let unit = wparam as u16;
+         if unit >= 0xD800 && unit < 0xDC00 {
+            HIGH_SURROGATE = unit;
+         } else {
+            let units = if unit >= 0xDC00 && unit < 0xE000 { [HIGH_SURROGATE, unit] } else { [unit, 0] };
+            if let Some(Ok(ch)) = ::std::char::decode_utf16(units.iter().cloned()).next() {
+               WIN_EVENT = Some(Event::Text(ch));
+            }
+
+            HIGH_SURROGATE = 0;
+         }
       }
 
       winuser::WM_MOUSEMOVE => {
@@ -338,6 +374,21 @@ unsafe extern "system" fn wnd_callback(window: HWND, msg: UINT, wparam: WPARAM,
          WIN_EVENT = Some(Event::MouseDown(Mouse::Right));
       },
 
+      winuser::WM_XBUTTONUP | winuser::WM_XBUTTONDOWN => {
+         let button = if winuser::GET_XBUTTON_WPARAM(wparam) == winuser::XBUTTON1 { Mouse::X1 } else { Mouse::X2 };
+         WIN_EVENT = Some(if msg == winuser::WM_XBUTTONDOWN { Event::MouseDown(button) } else { Event::MouseUp(button) });
+      },
+
+      winuser::WM_MOUSEWHEEL => {
+         let lines = winuser::GET_WHEEL_DELTA_WPARAM(wparam) as f32 / winuser::WHEEL_DELTA as f32;
+         WIN_EVENT = Some(Event::MouseWheel(0.0, lines));
+      },
+
+      winuser::WM_MOUSEHWHEEL => {
+         let lines = winuser::GET_WHEEL_DELTA_WPARAM(wparam) as f32 / winuser::WHEEL_DELTA as f32;
+         WIN_EVENT = Some(Event::MouseWheel(lines, 0.0));
+      },
+
       _ => (),
     }
 
@@ -352,6 +403,7 @@ fn keycode_win32_to_tiny(key_code: u8) -> Option<Key> {
 
       16 => Some(Key::LShift),
       17 => Some(Key::LControl),
+      18 => Some(Key::LAlt),
 
       27 => Some(Key::Escape),
 
@@ -368,6 +420,47 @@ fn keycode_win32_to_tiny(key_code: u8) -> Option<Key> {
       45 => Some(Key::Insert),
       46 => Some(Key::Delete),
 
+      48 => Some(Key::Key0),
+      49 => Some(Key::Key1),
+      50 => Some(Key::Key2),
+      51 => Some(Key::Key3),
+      52 => Some(Key::Key4),
+      53 => Some(Key::Key5),
+      54 => Some(Key::Key6),
+      55 => Some(Key::Key7),
+      56 => Some(Key::Key8),
+      57 => Some(Key::Key9),
+
+      65 => Some(Key::A),
+      66 => Some(Key::B),
+      67 => Some(Key::C),
+      68 => Some(Key::D),
+      69 => Some(Key::E),
+      70 => Some(Key::F),
+      71 => Some(Key::G),
+      72 => Some(Key::H),
+      73 => Some(Key::I),
+      74 => Some(Key::J),
+      75 => Some(Key::K),
+      76 => Some(Key::L),
+      77 => Some(Key::M),
+      78 => Some(Key::N),
+      79 => Some(Key::O),
+      80 => Some(Key::P),
+      81 => Some(Key::Q),
+      82 => Some(Key::R),
+      83 => Some(Key::S),
+      84 => Some(Key::T),
+      85 => Some(Key::U),
+      86 => Some(Key::V),
+      87 => Some(Key::W),
+      88 => Some(Key::X),
+      89 => Some(Key::Y),
+      90 => Some(Key::Z),
+
+      91 => Some(Key::LWin),
+      92 => Some(Key::RWin),
+
       112 => Some(Key::F1),
       113 => Some(Key::F2),
       114 => Some(Key::F3),
@@ -0,0 +1,206 @@
+use super::*;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use gif;
+use gif::SetParameter;
+
+/// Encodes canvas frames into an animated GIF.
+///
+/// The canvas is already palette indexed, so frames are written as they are. The file
+/// uses the palette of the first frame, later frames carry their own palette if it changed.
+/// Identical consecutive frames are merged into one longer frame.
+pub struct GifRecorder {
+   path: PathBuf,
+   encoder: Option<gif::Encoder<BufWriter<File>>>,
+   file: Option<File>,
+   global_palette: Vec<u8>,
+
+   pending: Vec<u8>,
+   pending_palette: Vec<u8>,
+   pending_frames: u32,
+   pending_time: f64,
+   frames: u32,
+   time: f64,
+   written_delay: u32,
+   width: u32,
+   height: u32,
+}
+
+impl GifRecorder {
+   pub fn new<P: AsRef<Path>>(path: P) -> Result<GifRecorder, String> {
+      let path = path.as_ref().to_path_buf();
+
+      let file = match File::create(&path) {
+         Ok(file) => file,
+         Err(err) => return Err(format!("Could not create {}: {}", path.display(), err)),
+      };
+
+      Ok(GifRecorder {
+         path: path,
+         encoder: None,
+         file: Some(file),
+         global_palette: Vec::new(),
+         pending: Vec::new(),
+         pending_palette: Vec::new(),
+         pending_frames: 0,
+         pending_time: 0.0,
+         frames: 0,
+         time: 0.0,
+         written_delay: 0,
+         width: 0,
+         height: 0,
+      })
+   }
+
+   pub fn path(&self) -> &Path {
+      &self.path
+   }
+
+   /// Number of frames recorded so far, including merged ones.
+   pub fn frames(&self) -> u32 {
+      self.frames + self.pending_frames
+   }
+
+   /// Adds a frame that is shown for `duration` seconds, all frames must have the same size.
+   pub fn frame(&mut self, canvas: &Bitmap, colors: &[Color], duration: f64) -> Result<(), String> {
+      if canvas.width > u16::max_value() as u32 || canvas.height > u16::max_value() as u32 {
+         return Err(format!("Canvas of {}x{} is too large for a GIF", canvas.width, canvas.height));
+      }
+
+      if self.encoder.is_none() {
+         self.width = canvas.width;
+         self.height = canvas.height;
+         self.global_palette = gif_palette(colors);
+
+         let file = self.file.take().unwrap();
+         let encoder = gif::Encoder::new(BufWriter::new(file), self.width as u16, self.height as u16, &self.global_palette)
+            .and_then(|mut encoder| encoder.set(gif::Repeat::Infinite).map(|_| encoder));
+
+         self.encoder = Some(self.check(encoder)?);
+      } else if canvas.width != self.width || canvas.height != self.height {
+         return Err(format!("Frame is {}x{} but the recording is {}x{}", canvas.width, canvas.height, self.width, self.height));
+      }
+
+      let palette = gif_palette(colors);
+      let pixels = canvas.pixels.borrow();
+
+      if self.pending_frames > 0 && *pixels == self.pending[..] && palette == self.pending_palette {
+         self.pending_frames += 1;
+         self.pending_time += duration;
+         return Ok(());
+      }
+
+      self.flush()?;
+
+      self.pending.clear();
+      self.pending.extend_from_slice(&pixels);
+      self.pending_palette = palette;
+      self.pending_frames = 1;
+      self.pending_time = duration;
+      Ok(())
+   }
+
+   /// Writes the last frame and closes the file.
+   pub fn finish(mut self) -> Result<(), String> {
+      self.flush()?;
+
+      // Dropping the encoder writes the trailer
+      self.encoder.take();
+      Ok(())
+   }
+
+   fn flush(&mut self) -> Result<(), String> {
+      if self.pending_frames == 0 {
+         return Ok(());
+      }
+
+      self.frames += self.pending_frames;
+      self.time += self.pending_time;
+      self.pending_frames = 0;
+
+      // Delays are in hundredths of a second, keep track of the total so rounding errors don't add up
+      let total_delay = (self.time * 100.0).round() as u32;
+      let delay = total_delay - self.written_delay;
+      self.written_delay = total_delay;
+
+      let frame = gif::Frame {
+         delay: cmp::min(delay, u16::max_value() as u32) as u16,
+         width: self.width as u16,
+         height: self.height as u16,
+         palette: if self.pending_palette != self.global_palette { Some(self.pending_palette.clone()) } else { None },
+         buffer: self.pending[..].into(),
+         ..gif::Frame::default()
+      };
+
+      let result = self.encoder.as_mut().unwrap().write_frame(&frame);
+      self.check(result)
+   }
+
+   fn check<T>(&self, result: ::std::io::Result<T>) -> Result<T, String> {
+      result.map_err(|err| format!("Could not write {}: {}", self.path.display(), err))
+   }
+}
+
+/// GIF colour tables hold RGB triplets, always use all 256 entries so any index is valid.
+fn gif_palette(colors: &[Color]) -> Vec<u8> {
+   let mut palette = Vec::with_capacity(256 * 3);
+
+   for color in colors.iter().take(256) {
+      palette.extend_from_slice(&[color.red(), color.green(), color.blue()]);
+   }
+
+   palette.resize(256 * 3, 0);
+   palette
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use palette::dawn_bringer as pal;
+
+   use std::env;
+   use std::fs;
+   use std::io::Read;
+
+   #[test]
+   fn records_frames() {
+      let path = env::temp_dir().join(format!("tiny-recorder-{}.gif", std::process::id()));
+      let palette = pal::create_palette();
+      let mut canvas = Bitmap::new(8, 4);
+
+      let mut recorder = GifRecorder::new(&path).unwrap();
+      for frame in 0..6 {
+         {
+            let painter = BitmapPainter::new(&mut canvas);
+            painter.clear(pal::BLACK);
+            painter.pixel(frame / 2, 1, pal::WHITE);
+         }
+         recorder.frame(&canvas, &palette.colors, 1.0 / 30.0).unwrap();
+      }
+
+      assert_eq!(recorder.frames(), 6);
+      assert!(recorder.frame(&Bitmap::new(4, 4), &palette.colors, 1.0 / 30.0).is_err());
+      recorder.finish().unwrap();
+
+      let mut decoder = gif::Decoder::new(fs::File::open(&path).unwrap()).read_info().unwrap();
+      let mut delays = Vec::new();
+      while let Some(frame) = decoder.read_next_frame().unwrap() {
+         assert_eq!(frame.buffer[8 + delays.len()], pal::WHITE);
+         delays.push(frame.delay);
+      }
+
+      // Pairs of identical frames are merged, 6 frames at 30 fps last 20 hundredths
+      assert_eq!(delays.len(), 3);
+      assert_eq!(delays.iter().sum::<u16>(), 20);
+
+      let mut bytes = Vec::new();
+      fs::File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+      assert_eq!(bytes.last(), Some(&0x3b));
+
+      fs::remove_file(&path).unwrap();
+   }
+}
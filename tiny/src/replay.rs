@@ -0,0 +1,372 @@
+use super::*;
+
+use platform::Input;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+const MAGIC: &'static [u8; 8] = b"TINYINP1";
+
+// Which parts of the input changed during a frame
+const CLOSED: u8 = 1;
+const KEYS: u8 = 2;
+const BUTTONS: u8 = 4;
+const POSITION: u8 = 8;
+const MOTION: u8 = 16;
+const WHEEL: u8 = 32;
+const TEXT: u8 = 64;
+
+
+/// Writes the input every frame ends up with after pumping the window, see
+/// `Context::start_input_recording`.
+///
+/// Each frame is stored as the time it covered plus only the parts of the input that
+/// changed while pumping, so idle frames take 9 bytes.
+pub struct InputRecorder {
+   path: PathBuf,
+   writer: BufWriter<File>,
+   frames: u64,
+}
+
+impl InputRecorder {
+   /// Creates the file, the input at the start of the first frame is stored as the
+   /// state playback starts from.
+   pub fn new<P: AsRef<Path>>(path: P, width: u32, height: u32) -> Result<InputRecorder, String> {
+      let path = path.as_ref().to_path_buf();
+
+      let file = match File::create(&path) {
+         Ok(file) => file,
+         Err(err) => return Err(format!("Could not create {}: {}", path.display(), err)),
+      };
+
+      let mut recorder = InputRecorder {
+         path: path,
+         writer: BufWriter::new(file),
+         frames: 0,
+      };
+
+      let mut header = MAGIC.to_vec();
+      header.extend_from_slice(&width.to_le_bytes());
+      header.extend_from_slice(&height.to_le_bytes());
+
+      recorder.write(&header)?;
+      Ok(recorder)
+   }
+
+   pub fn path(&self) -> &Path {
+      &self.path
+   }
+
+   pub fn frames(&self) -> u64 {
+      self.frames
+   }
+
+   /// Adds a frame that took `elapsed` seconds and turned `before` into `after`.
+   pub(crate) fn frame(&mut self, elapsed: f64, before: &Input, after: &Input, closed: bool) -> Result<(), String> {
+      let mut buffer = Vec::new();
+      if self.frames == 0 {
+         encode(&mut buffer, &Input::new(), before, false);
+      }
+
+      buffer.extend_from_slice(&elapsed.to_bits().to_le_bytes());
+      encode(&mut buffer, before, after, closed);
+
+      self.frames += 1;
+      self.write(&buffer)
+   }
+
+   pub fn finish(mut self) -> Result<(), String> {
+      let result = self.writer.flush();
+      result.map_err(|err| format!("Could not write {}: {}", self.path.display(), err))
+   }
+
+   fn write(&mut self, buffer: &[u8]) -> Result<(), String> {
+      let result = self.writer.write_all(buffer);
+      result.map_err(|err| format!("Could not write {}: {}", self.path.display(), err))
+   }
+}
+
+
+/// Feeds recorded input back in place of what the window reports, see
+/// `Context::start_input_playback`.
+///
+/// Replaying reproduces the application as long as it starts out in the same state as
+/// when recording began, which is easiest to get by recording and replaying from launch.
+pub struct InputPlayback {
+   path: PathBuf,
+   data: Vec<u8>,
+   pos: usize,
+   frames: u64,
+}
+
+impl InputPlayback {
+   /// Loads a recording made for a canvas of `width` x `height`.
+   pub fn load<P: AsRef<Path>>(path: P, width: u32, height: u32) -> Result<InputPlayback, String> {
+      let path = path.as_ref().to_path_buf();
+
+      let mut data = Vec::new();
+      if let Err(err) = File::open(&path).and_then(|mut f| f.read_to_end(&mut data)) {
+         return Err(format!("Could not load input recording {}: {}", path.display(), err));
+      }
+
+      let mut playback = InputPlayback {
+         path: path,
+         data: data,
+         pos: 0,
+         frames: 0,
+      };
+
+      if playback.read(MAGIC.len())? != MAGIC {
+         return Err(playback.error("not an input recording"));
+      }
+
+      let size = (playback.read_u32()?, playback.read_u32()?);
+      if size != (width, height) {
+         return Err(playback.error(&format!("recorded at {}x{}, not {}x{}", size.0, size.1, width, height)));
+      }
+
+      if playback.finished() {
+         return Ok(playback);
+      }
+
+      // Check every frame up front so playback can't fail half way
+      let start = playback.pos;
+      let mut input = Input::new();
+      playback.decode(&mut input)?;
+      while !playback.finished() {
+         playback.read(8)?;
+         playback.decode(&mut input)?;
+      }
+
+      playback.pos = start;
+      Ok(playback)
+   }
+
+   pub fn path(&self) -> &Path {
+      &self.path
+   }
+
+   /// Number of frames played back so far.
+   pub fn frames(&self) -> u64 {
+      self.frames
+   }
+
+   pub fn finished(&self) -> bool {
+      self.pos >= self.data.len()
+   }
+
+   /// Applies the next frame to `input`, which should be in the state it had before the window
+   /// was pumped. Returns the elapsed time and whether the window was closed, or `None` at the end.
+   ///
+   /// The first frame starts over from the input recording started with.
+   pub(crate) fn frame(&mut self, input: &mut Input) -> Option<(f64, bool)> {
+      if self.finished() {
+         return None;
+      }
+
+      if self.frames == 0 {
+         *input = Input::new();
+         self.decode(input).unwrap();
+      }
+
+      let elapsed = f64::from_bits(self.read_u64().unwrap());
+      let closed = self.decode(input).unwrap();
+
+      self.frames += 1;
+      Some((elapsed, closed))
+   }
+
+   fn decode(&mut self, input: &mut Input) -> Result<bool, String> {
+      let changed = self.read(1)?[0];
+
+      if changed & KEYS != 0 {
+         let count = self.read(1)?[0] as usize + 1;
+         for _ in 0..count {
+            let key = self.read(2)?;
+            input.key_state[key[0] as usize] = key[1] & 1 != 0;
+            input.key_delta[key[0] as usize] = key[1] & 2 != 0;
+         }
+      }
+
+      if changed & BUTTONS != 0 {
+         let buttons = self.read(2)?;
+         for button in 0..input.mouse_state.len() {
+            input.mouse_state[button] = buttons[0] & (1 << button) != 0;
+            input.mouse_delta[button] = buttons[1] & (1 << button) != 0;
+         }
+      }
+
+      if changed & POSITION != 0 {
+         input.mouse_x = self.read_u32()?;
+         input.mouse_y = self.read_u32()?;
+      }
+
+      if changed & MOTION != 0 {
+         input.mouse_dx = self.read_u32()? as i32;
+         input.mouse_dy = self.read_u32()? as i32;
+      }
+
+      if changed & WHEEL != 0 {
+         input.wheel_x = f32::from_bits(self.read_u32()?);
+         input.wheel_y = f32::from_bits(self.read_u32()?);
+      }
+
+      if changed & TEXT != 0 {
+         let len = self.read_u32()? as usize;
+         let text = match String::from_utf8(self.read(len)?.to_vec()) {
+            Ok(text) => text,
+            Err(_) => return Err(self.error("text input is not UTF-8")),
+         };
+
+         input.text_input = text.chars().collect();
+      }
+
+      Ok(changed & CLOSED != 0)
+   }
+
+   fn read(&mut self, len: usize) -> Result<&[u8], String> {
+      if self.data.len() - self.pos < len {
+         return Err(self.error("unexpected end of file"));
+      }
+
+      self.pos += len;
+      Ok(&self.data[self.pos - len..self.pos])
+   }
+
+   fn read_u32(&mut self) -> Result<u32, String> {
+      let mut bytes = [0; 4];
+      bytes.copy_from_slice(self.read(4)?);
+      Ok(u32::from_le_bytes(bytes))
+   }
+
+   fn read_u64(&mut self) -> Result<u64, String> {
+      let mut bytes = [0; 8];
+      bytes.copy_from_slice(self.read(8)?);
+      Ok(u64::from_le_bytes(bytes))
+   }
+
+   fn error(&self, msg: &str) -> String {
+      format!("Could not load input recording {}: {}", self.path.display(), msg)
+   }
+}
+
+
+/// Appends the parts of `after` that differ from `before`.
+fn encode(buffer: &mut Vec<u8>, before: &Input, after: &Input, closed: bool) {
+   let mut changed = if closed { CLOSED } else { 0 };
+   let mut data = Vec::new();
+
+   let keys = (0..after.key_state.len())
+      .filter(|&key| before.key_state[key] != after.key_state[key] || before.key_delta[key] != after.key_delta[key])
+      .collect::<Vec<_>>();
+
+   if !keys.is_empty() {
+      changed |= KEYS;
+      data.push((keys.len() - 1) as u8);
+      for key in keys {
+         data.push(key as u8);
+         data.push(after.key_state[key] as u8 | (after.key_delta[key] as u8) << 1);
+      }
+   }
+
+   if before.mouse_state != after.mouse_state || before.mouse_delta != after.mouse_delta {
+      let bits = |buttons: &[bool]| buttons.iter().enumerate().fold(0, |bits, (idx, &down)| bits | (down as u8) << idx);
+
+      changed |= BUTTONS;
+      data.push(bits(&after.mouse_state));
+      data.push(bits(&after.mouse_delta));
+   }
+
+   if (before.mouse_x, before.mouse_y) != (after.mouse_x, after.mouse_y) {
+      changed |= POSITION;
+      data.extend_from_slice(&after.mouse_x.to_le_bytes());
+      data.extend_from_slice(&after.mouse_y.to_le_bytes());
+   }
+
+   if (before.mouse_dx, before.mouse_dy) != (after.mouse_dx, after.mouse_dy) {
+      changed |= MOTION;
+      data.extend_from_slice(&after.mouse_dx.to_le_bytes());
+      data.extend_from_slice(&after.mouse_dy.to_le_bytes());
+   }
+
+   if (before.wheel_x, before.wheel_y) != (after.wheel_x, after.wheel_y) {
+      changed |= WHEEL;
+      data.extend_from_slice(&after.wheel_x.to_bits().to_le_bytes());
+      data.extend_from_slice(&after.wheel_y.to_bits().to_le_bytes());
+   }
+
+   if before.text_input != after.text_input {
+      let text = after.text_input.iter().collect::<String>();
+
+      changed |= TEXT;
+      data.extend_from_slice(&(text.len() as u32).to_le_bytes());
+      data.extend_from_slice(text.as_bytes());
+   }
+
+   buffer.push(changed);
+   buffer.extend_from_slice(&data);
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use platform::Viewport;
+
+   use std::env;
+   use std::fs;
+
+   #[test]
+   fn round_trip() {
+      let path = env::temp_dir().join(format!("tiny-input-{}.rec", std::process::id()));
+      let mut recorder = InputRecorder::new(&path, 16, 8).unwrap();
+
+      let mut start = Input::new();
+      start.set_key(Key::LShift, true);
+
+      let mut input = start.clone();
+      input.set_key(Key::A, true);
+      input.set_mouse(Mouse::X1, true);
+      input.move_mouse(&Viewport::new(16, 8, 16, 8, Scaling::Fit), 3.0, 5.0);
+      input.scroll(0.0, -1.5);
+      input.text_input.push('a');
+      recorder.frame(0.25, &start, &input, false).unwrap();
+
+      let after = input.clone();
+      input.clear_deltas();
+      recorder.frame(0.5, &input, &input, false).unwrap();
+      recorder.frame(0.5, &input, &input, true).unwrap();
+      assert_eq!(recorder.frames(), 3);
+      recorder.finish().unwrap();
+
+      // Idle frames only store their time and an empty change mask
+      let size = fs::metadata(&path).unwrap().len();
+      assert_eq!(size, 16 + 4 + (9 + 3 + 2 + 8 + 8 + 8 + 5) + 9 + 9);
+
+      assert!(InputPlayback::load(&path, 16, 9).is_err());
+
+      let mut playback = InputPlayback::load(&path, 16, 8).unwrap();
+      let mut replayed = Input::new();
+      replayed.set_key(Key::Z, true);
+
+      assert_eq!(playback.frame(&mut replayed), Some((0.25, false)));
+      assert!(replayed.key_state[..] == after.key_state[..] && replayed.key_delta[..] == after.key_delta[..]);
+      assert_eq!((replayed.mouse_state, replayed.mouse_delta), (after.mouse_state, after.mouse_delta));
+      assert_eq!((replayed.mouse_x, replayed.mouse_y, replayed.mouse_dx, replayed.mouse_dy), (3, 5, 3, 5));
+      assert_eq!((replayed.wheel_x, replayed.wheel_y), (0.0, -1.5));
+      assert_eq!(replayed.text_input, ['a']);
+
+      replayed.clear_deltas();
+      assert_eq!(playback.frame(&mut replayed), Some((0.5, false)));
+      assert_eq!(playback.frame(&mut replayed), Some((0.5, true)));
+      assert_eq!(playback.frame(&mut replayed), None);
+      assert!(playback.finished());
+
+      // A truncated file is refused when loading
+      let data = fs::read(&path).unwrap();
+      fs::write(&path, &data[..data.len() - 4]).unwrap();
+      assert!(InputPlayback::load(&path, 16, 8).is_err());
+
+      fs::remove_file(&path).unwrap();
+   }
+}
@@ -0,0 +1,143 @@
+use super::*;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use image;
+
+/// When this environment variable is set golden images are rewritten instead of compared.
+pub const SNAPSHOT_UPDATE_VAR: &'static str = "TINY_UPDATE_SNAPSHOTS";
+
+/// Golden image testing for anything painted through a `Painter`.
+///
+/// Each snapshot is stored as `<dir>/<name>.png`. On a mismatch the rendered image is
+/// written to `<name>.actual.png` and the differing pixels are marked in `<name>.diff.png`.
+pub struct Snapshot {
+   dir: PathBuf,
+   palette: Palette,
+}
+
+impl Snapshot {
+   pub fn new<P: AsRef<Path>>(dir: P, palette: Palette) -> Snapshot {
+      Snapshot {
+         dir: dir.as_ref().to_path_buf(),
+         palette: palette,
+      }
+   }
+
+   /// Paints into a cleared canvas of the given size and compares it against the golden image.
+   pub fn paint<F>(&self, name: &str, width: u32, height: u32, f: F) -> Result<(), String>
+      where F: FnOnce(&Painter)
+   {
+      let mut bitmap = Bitmap::new(width, height);
+
+      {
+         let painter = BitmapPainter::new(&mut bitmap);
+         painter.palette(Some(Rc::new(self.palette.clone())));
+         f(&painter);
+      }
+
+      self.compare(name, &bitmap)
+   }
+
+   pub fn compare(&self, name: &str, bitmap: &Bitmap) -> Result<(), String> {
+      let golden_path = self.dir.join(format!("{}.png", name));
+      let actual_path = self.dir.join(format!("{}.actual.png", name));
+      let diff_path = self.dir.join(format!("{}.diff.png", name));
+
+      let actual = bitmap.to_rgba(&self.palette);
+
+      if env::var_os(SNAPSHOT_UPDATE_VAR).is_some() {
+         save(&golden_path, &actual, bitmap.width, bitmap.height)?;
+         remove_stale(&actual_path, &diff_path);
+         return Ok(());
+      }
+
+      if !golden_path.exists() {
+         save(&golden_path, &actual, bitmap.width, bitmap.height)?;
+         return Err(format!("No golden image for '{}', recorded a new one at {}", name, golden_path.display()));
+      }
+
+      let golden = match image::open(&golden_path) {
+         Ok(img) => img.to_rgba(),
+         Err(err) => return Err(format!("Could not load golden image {}: {}", golden_path.display(), err)),
+      };
+
+      if golden.width() != bitmap.width || golden.height() != bitmap.height {
+         save(&actual_path, &actual, bitmap.width, bitmap.height)?;
+         return Err(format!("Snapshot '{}' is {}x{} but the golden image is {}x{}, see {}",
+                            name, bitmap.width, bitmap.height, golden.width(), golden.height(), actual_path.display()));
+      }
+
+      let golden = golden.into_raw();
+      let mut diff = Vec::with_capacity(actual.len());
+      let mut mismatches = 0;
+
+      for (a, g) in actual.chunks(4).zip(golden.chunks(4)) {
+         if a == g {
+            // Keep a faded version of the image around for reference
+            let luma = ((g[0] as u32 + g[1] as u32 + g[2] as u32) / 12) as u8;
+            diff.extend_from_slice(&[luma, luma, luma, 255]);
+         } else {
+            diff.extend_from_slice(&[255, 0, 255, 255]);
+            mismatches += 1;
+         }
+      }
+
+      if mismatches > 0 {
+         save(&actual_path, &actual, bitmap.width, bitmap.height)?;
+         save(&diff_path, &diff, bitmap.width, bitmap.height)?;
+         return Err(format!("Snapshot '{}' differs from the golden image in {} pixels, see {}",
+                            name, mismatches, diff_path.display()));
+      }
+
+      remove_stale(&actual_path, &diff_path);
+      Ok(())
+   }
+}
+
+fn save(path: &Path, rgba: &[u8], width: u32, height: u32) -> Result<(), String> {
+   if let Some(dir) = path.parent() {
+      if let Err(err) = fs::create_dir_all(dir) {
+         return Err(format!("Could not create {}: {}", dir.display(), err));
+      }
+   }
+
+   match image::save_buffer(path, rgba, width, height, image::RGBA(8)) {
+      Ok(_) => Ok(()),
+      Err(err) => Err(format!("Could not save {}: {}", path.display(), err)),
+   }
+}
+
+fn remove_stale(actual_path: &Path, diff_path: &Path) {
+   let _ = fs::remove_file(actual_path);
+   let _ = fs::remove_file(diff_path);
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn mismatch_writes_diff() {
+      let dir = env::temp_dir().join(format!("tiny-snapshot-{}", std::process::id()));
+      let snapshot = Snapshot::new(&dir, palette::dawn_bringer::create_palette());
+
+      let _ = fs::remove_dir_all(&dir);
+      assert!(snapshot.paint("dot", 4, 4, |p| p.pixel(1, 1, WHITE)).is_err());
+      assert!(snapshot.paint("dot", 4, 4, |p| p.pixel(1, 1, WHITE)).is_ok());
+      assert!(snapshot.paint("dot", 4, 4, |p| p.pixel(2, 1, WHITE)).is_err());
+      assert!(dir.join("dot.diff.png").exists());
+      assert!(dir.join("dot.actual.png").exists());
+
+      let diff = image::open(dir.join("dot.diff.png")).unwrap().to_rgba();
+      assert_eq!(diff.get_pixel(1, 1).data, [255, 0, 255, 255]);
+      assert_eq!(diff.get_pixel(2, 1).data, [255, 0, 255, 255]);
+      assert_eq!(diff.get_pixel(0, 0).data, [0, 0, 0, 255]);
+
+      fs::remove_dir_all(&dir).unwrap();
+   }
+}
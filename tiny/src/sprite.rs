@@ -0,0 +1,507 @@
+use super::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use json::Json;
+
+/// A region of a sprite sheet bitmap.
+///
+/// The pivot is the point inside `rect` that ends up at the position the frame is drawn at,
+/// for example the feet of a unit. It may lie outside `rect` for trimmed frames.
+#[derive(Copy, Clone, PartialEq)]
+pub struct SpriteFrame {
+   pub rect: Rect,
+   pub pivot_x: i32,
+   pub pivot_y: i32,
+}
+
+impl SpriteFrame {
+   pub fn new(rect: Rect) -> SpriteFrame {
+      SpriteFrame {
+         rect: rect,
+         pivot_x: 0,
+         pivot_y: 0,
+      }
+   }
+
+   pub fn with_pivot(mut self, pivot_x: i32, pivot_y: i32) -> SpriteFrame {
+      self.pivot_x = pivot_x;
+      self.pivot_y = pivot_y;
+      self
+   }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LoopMode {
+   /// Plays once and stays on the last frame.
+   Once,
+   Loop,
+   /// Plays forwards then backwards, without repeating the first and last frame.
+   PingPong,
+}
+
+/// A frame of an animation, `frame` indexes the frames of the sprite sheet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AnimationFrame {
+   pub frame: usize,
+   /// Seconds the frame is shown.
+   pub duration: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+   pub frames: Vec<AnimationFrame>,
+   pub mode: LoopMode,
+}
+
+impl Animation {
+   /// Shows each of `frames` for `duration` seconds, change single frames with `with_duration`.
+   pub fn new(frames: &[usize], duration: f64, mode: LoopMode) -> Animation {
+      Animation {
+         frames: frames.iter().map(|&frame| AnimationFrame { frame: frame, duration: duration }).collect(),
+         mode: mode,
+      }
+   }
+
+   /// Sets how long the frame at `idx` in the animation is shown.
+   pub fn with_duration(mut self, idx: usize, duration: f64) -> Animation {
+      if let Some(frame) = self.frames.get_mut(idx) {
+         frame.duration = duration;
+      }
+      self
+   }
+
+   /// Seconds until a `Once` animation ends or any other animation starts over.
+   pub fn length(&self) -> f64 {
+      let forward = self.frames.iter().map(|frame| frame.duration).sum::<f64>();
+
+      match self.mode {
+         LoopMode::PingPong if self.frames.len() > 2 => {
+            let inner = &self.frames[1..self.frames.len() - 1];
+            forward + inner.iter().map(|frame| frame.duration).sum::<f64>()
+         },
+         _ => forward,
+      }
+   }
+
+   /// Sprite sheet frame shown `time` seconds after the animation started.
+   pub fn frame_at(&self, time: f64) -> Option<usize> {
+      let length = self.length();
+      let last = self.frames.last()?.frame;
+
+      let mut time = match self.mode {
+         _ if length <= 0.0 => 0.0,
+         LoopMode::Once if time >= length => return Some(last),
+         LoopMode::Once => time,
+         LoopMode::Loop | LoopMode::PingPong => time.max(0.0) % length,
+      };
+
+      let count = self.frames.len();
+      let back = if self.mode == LoopMode::PingPong && count > 2 { count - 2 } else { 0 };
+      let order = (0..count).chain((1..back + 1).rev());
+
+      for idx in order {
+         let frame = &self.frames[idx];
+         if time < frame.duration {
+            return Some(frame.frame);
+         }
+
+         time -= frame.duration;
+      }
+
+      Some(last)
+   }
+
+   /// Whether a `Once` animation has ended by `time`, other animations never end.
+   pub fn finished(&self, time: f64) -> bool {
+      self.mode == LoopMode::Once && time >= self.length()
+   }
+}
+
+
+/// A bitmap sliced into frames, along with the animations made from them.
+#[derive(Clone)]
+pub struct SpriteSheet {
+   pub bitmap: Bitmap,
+
+   frames: Vec<SpriteFrame>,
+   frame_names: HashMap<String, usize>,
+   animations: HashMap<String, Animation>,
+}
+
+impl SpriteSheet {
+   /// A sprite sheet without any frames yet, see `add_frame`.
+   pub fn new(bitmap: Bitmap) -> SpriteSheet {
+      SpriteSheet {
+         bitmap: bitmap,
+         frames: Vec::new(),
+         frame_names: HashMap::new(),
+         animations: HashMap::new(),
+      }
+   }
+
+   /// Slices the bitmap into `frame_width` x `frame_height` frames, row by row. The first
+   /// frames are given `names`, the rest are named by their index.
+   pub fn from_grid(bitmap: Bitmap, frame_width: u32, frame_height: u32, names: &[&str]) -> SpriteSheet {
+      let mut sheet = SpriteSheet::new(bitmap);
+
+      let columns = sheet.bitmap.width / cmp::max(frame_width, 1);
+      let count = columns * (sheet.bitmap.height / cmp::max(frame_height, 1));
+
+      for idx in 0..count {
+         let x = idx % columns * frame_width;
+         let y = idx / columns * frame_height;
+         let name = names.get(idx as usize).map_or_else(|| idx.to_string(), |name| name.to_string());
+
+         sheet.add_frame(&name, SpriteFrame::new(Rect::new_size(x as i32, y as i32, frame_width as i32, frame_height as i32)));
+      }
+
+      sheet
+   }
+
+   /// Adds a frame and returns its index. A frame already called `name` keeps its index
+   /// but can no longer be found by name.
+   pub fn add_frame(&mut self, name: &str, frame: SpriteFrame) -> usize {
+      self.frames.push(frame);
+      self.frame_names.insert(name.to_string(), self.frames.len() - 1);
+      self.frames.len() - 1
+   }
+
+   pub fn frames(&self) -> &[SpriteFrame] {
+      &self.frames
+   }
+
+   pub fn frame(&self, idx: usize) -> Option<&SpriteFrame> {
+      self.frames.get(idx)
+   }
+
+   pub fn frame_mut(&mut self, idx: usize) -> Option<&mut SpriteFrame> {
+      self.frames.get_mut(idx)
+   }
+
+   /// Index of the frame called `name`.
+   pub fn find_frame(&self, name: &str) -> Option<usize> {
+      self.frame_names.get(name).cloned()
+   }
+
+   /// Sets the pivot of every frame.
+   pub fn set_pivot(&mut self, pivot_x: i32, pivot_y: i32) {
+      for frame in self.frames.iter_mut() {
+         frame.pivot_x = pivot_x;
+         frame.pivot_y = pivot_y;
+      }
+   }
+
+   /// Adds or replaces the animation called `name`.
+   pub fn add_animation(&mut self, name: &str, animation: Animation) {
+      self.animations.insert(name.to_string(), animation);
+   }
+
+   pub fn animation(&self, name: &str) -> Option<&Animation> {
+      self.animations.get(name)
+   }
+
+   /// Names of all animations, sorted.
+   pub fn animation_names(&self) -> Vec<&str> {
+      let mut names = self.animations.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+      names.sort();
+      names
+   }
+
+   /// Draws frame `idx` with its pivot at `x`, `y`. The pivot is mirrored along with the
+   /// frame by `DRAW_FLIP_H` and `DRAW_FLIP_V`, see `Painter::blit` for the other arguments.
+   pub fn paint(&self, painter: &Painter, idx: usize, x: i32, y: i32, flags: u32, color: u8) {
+      let frame = match self.frames.get(idx) {
+         Some(frame) => frame,
+         None => return,
+      };
+
+      let pivot_x = if flags & DRAW_FLIP_H != 0 { frame.rect.width() - frame.pivot_x } else { frame.pivot_x };
+      let pivot_y = if flags & DRAW_FLIP_V != 0 { frame.rect.height() - frame.pivot_y } else { frame.pivot_y };
+
+      painter.blit(x - pivot_x, y - pivot_y, &self.bitmap, frame.rect, flags, color);
+   }
+
+   /// Loads a sprite sheet exported from Aseprite as JSON, in either the hash or the array
+   /// layout, along with the image it names. Colours are added to the palette like `Bitmap::load`.
+   ///
+   /// Frames are named by their filename, tags become animations and the pivot of the first
+   /// slice that has one is used for the frames its keys cover. Tags with a repeat count of
+   /// one play `Once`, other repeat counts are played as `Loop`.
+   pub fn load_aseprite(ctx: &Context, path: &Path) -> Result<SpriteSheet, String> {
+      let mut text = String::new();
+      if let Err(err) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+         return Err(format!("Could not load sprite sheet {}: {}", path.display(), err));
+      }
+
+      let dir = path.parent().unwrap_or(Path::new(""));
+      SpriteSheet::parse_aseprite(&text, |image| Bitmap::load(ctx, &dir.join(image)))
+         .map_err(|err| format!("Could not load sprite sheet {}: {}", path.display(), err))
+   }
+
+   /// Parses an Aseprite JSON export, `load_image` is given the image file named in it.
+   pub fn parse_aseprite<F>(text: &str, load_image: F) -> Result<SpriteSheet, String>
+      where F: FnOnce(&str) -> Result<Bitmap, String>
+   {
+      let json = Json::parse(text)?;
+      let meta = json.get("meta").ok_or("Missing 'meta'")?;
+      let image = meta.get("image").and_then(Json::as_str).ok_or("Missing 'meta.image'")?;
+
+      let frames_json = json.get("frames");
+      let frames = if let Some(frames) = frames_json.and_then(Json::as_object) {
+         frames.iter().map(|member| (member.0.as_str(), &member.1)).collect::<Vec<_>>()
+      } else if let Some(frames) = frames_json.and_then(Json::as_array) {
+         frames.iter().map(|frame| (frame.get("filename").and_then(Json::as_str).unwrap_or(""), frame)).collect()
+      } else {
+         return Err(String::from("Missing 'frames'"));
+      };
+
+      let mut sheet = SpriteSheet::new(load_image(image)?);
+      let mut durations = Vec::new();
+
+      for (idx, &(name, frame)) in frames.iter().enumerate() {
+         if frame.get("rotated") == Some(&Json::Bool(true)) {
+            return Err(format!("Frame {} is rotated, which is not supported", idx));
+         }
+
+         let rect = frame.get("frame").and_then(json_rect).ok_or_else(|| format!("Frame {} has no 'frame' rect", idx))?;
+         let trim = frame.get("spriteSourceSize").and_then(json_rect).unwrap_or(Rect::new_size(0, 0, 0, 0));
+
+         sheet.add_frame(name, SpriteFrame::new(rect).with_pivot(-trim.left, -trim.top));
+         durations.push(frame.get("duration").and_then(Json::as_f64).unwrap_or(100.0) / 1000.0);
+      }
+
+      // Pivots are relative to the untrimmed frame, slice keys last until the next key
+      let pivot_slice = meta.get("slices").and_then(Json::as_array).unwrap_or(&[]).iter()
+         .find(|slice| slice.get("keys").and_then(Json::as_array).unwrap_or(&[]).iter().any(|key| key.get("pivot").is_some()));
+
+      if let Some(keys) = pivot_slice.and_then(|slice| slice.get("keys")).and_then(Json::as_array) {
+         for (idx, key) in keys.iter().enumerate() {
+            let start = key.get("frame").and_then(Json::as_i32).unwrap_or(0).max(0) as usize;
+            let end = keys.get(idx + 1).and_then(|next| next.get("frame")).and_then(Json::as_i32).map_or(sheet.frames.len(), |end| end as usize);
+
+            let bounds = key.get("bounds").and_then(json_rect).unwrap_or(Rect::new_size(0, 0, 0, 0));
+            let pivot = match key.get("pivot") {
+               Some(pivot) => (pivot.get("x").and_then(Json::as_i32).unwrap_or(0), pivot.get("y").and_then(Json::as_i32).unwrap_or(0)),
+               None => continue,
+            };
+
+            for frame in sheet.frames.iter_mut().take(end).skip(start) {
+               frame.pivot_x += bounds.left + pivot.0;
+               frame.pivot_y += bounds.top + pivot.1;
+            }
+         }
+      }
+
+      for tag in meta.get("frameTags").and_then(Json::as_array).unwrap_or(&[]) {
+         let name = tag.get("name").and_then(Json::as_str).ok_or("Tag without a name")?;
+         let from = tag.get("from").and_then(Json::as_i32).unwrap_or(0);
+         let to = tag.get("to").and_then(Json::as_i32).unwrap_or(from);
+
+         if from < 0 || to < from || to as usize >= sheet.frames.len() {
+            return Err(format!("Tag '{}' covers frames {} to {}, but there are {}", name, from, to, sheet.frames.len()));
+         }
+
+         let direction = tag.get("direction").and_then(Json::as_str).unwrap_or("forward");
+         let once = tag.get("repeat").and_then(Json::as_str) == Some("1");
+
+         let mut frames = (from as usize..to as usize + 1).collect::<Vec<_>>();
+         if direction.ends_with("reverse") {
+            frames.reverse();
+         }
+
+         let mode = match direction {
+            "pingpong" | "pingpong_reverse" => LoopMode::PingPong,
+            _ if once => LoopMode::Once,
+            _ => LoopMode::Loop,
+         };
+
+         sheet.add_animation(name, Animation {
+            frames: frames.into_iter().map(|frame| AnimationFrame { frame: frame, duration: durations[frame] }).collect(),
+            mode: mode,
+         });
+      }
+
+      Ok(sheet)
+   }
+}
+
+/// Reads an Aseprite `{"x", "y", "w", "h"}` object.
+fn json_rect(json: &Json) -> Option<Rect> {
+   let value = |key| json.get(key).and_then(Json::as_i32);
+   Some(Rect::new_size(value("x")?, value("y")?, value("w")?, value("h")?))
+}
+
+
+/// Plays the animations of a sprite sheet, switching between them by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationPlayer {
+   animation: String,
+   time: f64,
+}
+
+impl AnimationPlayer {
+   pub fn new(animation: &str) -> AnimationPlayer {
+      AnimationPlayer {
+         animation: animation.to_string(),
+         time: 0.0,
+      }
+   }
+
+   /// Switches to `animation`, starting it over unless it is already playing.
+   pub fn play(&mut self, animation: &str) {
+      if self.animation != animation {
+         self.animation = animation.to_string();
+         self.time = 0.0;
+      }
+   }
+
+   pub fn restart(&mut self) {
+      self.time = 0.0;
+   }
+
+   pub fn animation(&self) -> &str {
+      &self.animation
+   }
+
+   /// Seconds since the animation started.
+   pub fn time(&self) -> f64 {
+      self.time
+   }
+
+   /// Advances the animation by `dt` seconds, usually `Timestep::step_length`.
+   pub fn step(&mut self, dt: f64) {
+      self.time += dt;
+   }
+
+   /// Sprite sheet frame currently shown, `None` when `sheet` has no such animation.
+   pub fn frame(&self, sheet: &SpriteSheet) -> Option<usize> {
+      sheet.animation(&self.animation)?.frame_at(self.time)
+   }
+
+   pub fn finished(&self, sheet: &SpriteSheet) -> bool {
+      sheet.animation(&self.animation).map_or(true, |animation| animation.finished(self.time))
+   }
+
+   /// Draws the current frame, see `SpriteSheet::paint`.
+   pub fn paint(&self, painter: &Painter, sheet: &SpriteSheet, x: i32, y: i32, flags: u32, color: u8) {
+      if let Some(frame) = self.frame(sheet) {
+         sheet.paint(painter, frame, x, y, flags, color);
+      }
+   }
+}
+
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use palette::dawn_bringer as pal;
+
+   const ASEPRITE: &'static str = r##"{ "frames": {
+      "knight 0.aseprite": { "frame": { "x": 0, "y": 0, "w": 4, "h": 6 }, "rotated": false, "trimmed": true,
+         "spriteSourceSize": { "x": 2, "y": 1, "w": 4, "h": 6 }, "sourceSize": { "w": 8, "h": 8 }, "duration": 100 },
+      "knight 1.aseprite": { "frame": { "x": 4, "y": 0, "w": 4, "h": 6 }, "rotated": false, "trimmed": true,
+         "spriteSourceSize": { "x": 2, "y": 2, "w": 4, "h": 6 }, "sourceSize": { "w": 8, "h": 8 }, "duration": 200 },
+      "knight 2.aseprite": { "frame": { "x": 8, "y": 0, "w": 8, "h": 8 }, "rotated": false, "trimmed": false,
+         "spriteSourceSize": { "x": 0, "y": 0, "w": 8, "h": 8 }, "sourceSize": { "w": 8, "h": 8 }, "duration": 50 }
+   },
+   "meta": { "app": "http://www.aseprite.org/", "image": "knight.png", "size": { "w": 16, "h": 8 },
+      "frameTags": [
+         { "name": "walk", "from": 0, "to": 1, "direction": "forward" },
+         { "name": "attack", "from": 0, "to": 2, "direction": "reverse", "repeat": "1" },
+         { "name": "idle", "from": 0, "to": 2, "direction": "pingpong" }
+      ],
+      "slices": [
+         { "name": "feet", "color": "#0000ffff", "keys": [
+            { "frame": 0, "bounds": { "x": 0, "y": 0, "w": 8, "h": 8 }, "pivot": { "x": 4, "y": 7 } },
+            { "frame": 2, "bounds": { "x": 1, "y": 0, "w": 6, "h": 8 }, "pivot": { "x": 3, "y": 6 } }
+         ] }
+      ]
+   } }"##;
+
+   #[test]
+   fn animation() {
+      let walk = Animation::new(&[3, 4, 5], 0.5, LoopMode::Loop).with_duration(1, 1.0);
+      assert_eq!(walk.length(), 2.0);
+      assert_eq!([0.0, 0.6, 1.5, 1.9, 2.1].iter().map(|&t| walk.frame_at(t).unwrap()).collect::<Vec<_>>(), [3, 4, 5, 5, 3]);
+      assert!(!walk.finished(10.0));
+
+      let die = Animation::new(&[7, 8], 0.25, LoopMode::Once);
+      assert_eq!((die.frame_at(0.3), die.frame_at(9.0)), (Some(8), Some(8)));
+      assert!(!die.finished(0.4) && die.finished(0.5));
+
+      let bob = Animation::new(&[0, 1, 2, 3], 1.0, LoopMode::PingPong);
+      assert_eq!(bob.length(), 6.0);
+      assert_eq!((0..7).map(|t| bob.frame_at(t as f64).unwrap()).collect::<Vec<_>>(), [0, 1, 2, 3, 2, 1, 0]);
+
+      assert_eq!(Animation::new(&[], 1.0, LoopMode::Loop).frame_at(0.0), None);
+   }
+
+   #[test]
+   fn grid_and_player() {
+      let mut sheet = SpriteSheet::from_grid(Bitmap::new(12, 8), 4, 4, &["stand"]);
+      sheet.set_pivot(2, 3);
+      sheet.add_animation("walk", Animation::new(&[1, 2, 3], 0.1, LoopMode::Loop));
+
+      assert_eq!(sheet.frames().len(), 6);
+      assert_eq!((sheet.find_frame("stand"), sheet.find_frame("4"), sheet.find_frame("6")), (Some(0), Some(4), None));
+      assert!(sheet.frame(4).map(|frame| frame.rect) == Some(Rect::new_size(4, 4, 4, 4)));
+
+      let mut player = AnimationPlayer::new("walk");
+      player.step(0.15);
+      assert_eq!(player.frame(&sheet), Some(2));
+
+      player.play("walk");
+      assert_eq!(player.frame(&sheet), Some(2));
+      player.play("run");
+      assert_eq!((player.frame(&sheet), player.finished(&sheet)), (None, true));
+   }
+
+   #[test]
+   fn aseprite() {
+      let sheet = SpriteSheet::parse_aseprite(ASEPRITE, |image| {
+         assert_eq!(image, "knight.png");
+         Ok(Bitmap::new(16, 8))
+      }).unwrap();
+
+      assert_eq!(sheet.find_frame("knight 1.aseprite"), Some(1));
+      assert_eq!(sheet.animation_names(), ["attack", "idle", "walk"]);
+
+      let pivots = sheet.frames().iter().map(|frame| (frame.pivot_x, frame.pivot_y)).collect::<Vec<_>>();
+      assert_eq!(pivots, [(2, 6), (2, 5), (4, 6)]);
+
+      let walk = sheet.animation("walk").unwrap();
+      assert_eq!(walk.frames, [AnimationFrame { frame: 0, duration: 0.1 }, AnimationFrame { frame: 1, duration: 0.2 }]);
+      assert_eq!(walk.mode, LoopMode::Loop);
+
+      let attack = sheet.animation("attack").unwrap();
+      assert_eq!(attack.frames.iter().map(|frame| frame.frame).collect::<Vec<_>>(), [2, 1, 0]);
+      assert_eq!(attack.mode, LoopMode::Once);
+      assert_eq!(sheet.animation("idle").unwrap().mode, LoopMode::PingPong);
+
+      let bad = ASEPRITE.replace("\"to\": 1", "\"to\": 3");
+      assert_eq!(SpriteSheet::parse_aseprite(&bad, |_| Ok(Bitmap::new(16, 8))).err(), Some(String::from("Tag 'walk' covers frames 0 to 3, but there are 3")));
+   }
+
+   #[test]
+   fn paint_with_pivot() {
+      let bitmap = Bitmap::new(4, 2);
+      bitmap.pixels.borrow_mut()[0] = pal::WHITE;
+
+      let mut sheet = SpriteSheet::from_grid(bitmap, 2, 2, &[]);
+      sheet.set_pivot(1, 2);
+
+      let mut canvas = Bitmap::new(8, 8);
+      {
+         let painter = BitmapPainter::new(&mut canvas);
+         sheet.paint(&painter, 0, 4, 4, 0, 0);
+         sheet.paint(&painter, 0, 4, 5, DRAW_FLIP_H | DRAW_FLIP_V, 0);
+      }
+
+      assert_eq!(canvas.pixel(3, 2), pal::WHITE);
+      assert_eq!(canvas.pixel(4, 6), pal::WHITE);
+      assert_eq!(canvas.pixels.borrow().iter().filter(|&&p| p != 0).count(), 2);
+   }
+}